@@ -1,6 +1,50 @@
-use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+};
 
-use cedar::embeddings::{EmbeddingFunction, SentenceTransformerEmbeddings};
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use serde_json::json;
+
+use cedar_db::{
+    client::{Client, LocalClient},
+    db::{Db, DuckDB},
+    embeddings::{EmbeddingError, EmbeddingFunction, SentenceTransformerEmbeddings},
+    Document, Embedding, Include, QueryOptions,
+};
+
+const DOCUMENTS: usize = 100_000;
+const DIMENSION: usize = 64;
+
+// Pseudo random vectors derived from the text, so that large collections can be built without
+// running a model.
+struct HashEmbeddings;
+
+impl EmbeddingFunction for HashEmbeddings {
+    fn embed<I, S: AsRef<str> + Sync>(&self, texts: I) -> Result<Vec<Embedding>, EmbeddingError>
+    where
+        I: IntoIterator<Item = S>,
+    {
+        Ok(texts
+            .into_iter()
+            .map(|text| {
+                let mut hasher = DefaultHasher::new();
+                text.as_ref().hash(&mut hasher);
+                let mut state = hasher.finish() | 1;
+
+                (0..DIMENSION)
+                    .map(|_| {
+                        state ^= state << 13;
+                        state ^= state >> 7;
+                        state ^= state << 17;
+                        (state >> 40) as f32 / (1u64 << 24) as f32 - 0.5
+                    })
+                    .collect::<Vec<_>>()
+                    .into()
+            })
+            .collect())
+    }
+}
 
 pub fn sentence_transformer_benchmark(c: &mut Criterion) {
     c.bench_function("sentence transformer", |b| {
@@ -11,5 +55,83 @@ pub fn sentence_transformer_benchmark(c: &mut Criterion) {
     });
 }
 
-criterion_group!(benches, sentence_transformer_benchmark);
+pub fn query_benchmark(c: &mut Criterion) {
+    let db = DuckDB::new(Default::default()).unwrap();
+    db.init().unwrap();
+
+    let mut client = LocalClient::init(db, HashEmbeddings).unwrap();
+    let mut collection = client.create_collection("bench").unwrap();
+
+    let docs: Vec<_> = (0..DOCUMENTS)
+        .map(|i| Document::new(format!("document {i}"), json!({ "bucket": i % 10 })))
+        .collect();
+    for chunk in docs.chunks(10_000) {
+        collection.add_documents(chunk).unwrap();
+    }
+
+    let mut group = c.benchmark_group("query 100k documents");
+    group.sample_size(20);
+
+    for k in [10, 100] {
+        group.bench_with_input(BenchmarkId::new("unfiltered", k), &k, |b, &k| {
+            b.iter(|| {
+                black_box(collection.query_documents(&["query"], json!({}), k)).unwrap();
+            })
+        });
+
+        group.bench_with_input(BenchmarkId::new("filtered", k), &k, |b, &k| {
+            b.iter(|| {
+                black_box(collection.query_documents(&["query"], json!({ "bucket": 3 }), k))
+                    .unwrap();
+            })
+        });
+    }
+
+    group.finish();
+
+    // Baseline for the batched hydration of results: the index search alone, results hydrated
+    // with one statement per query batch, and results hydrated with one statement per result
+    // as queries did before.
+    let mut group = c.benchmark_group("hydrate results of 100k documents");
+    group.sample_size(20);
+
+    let index_only = QueryOptions {
+        include: Include::none(),
+        ..Default::default()
+    };
+    for k in [10, 100] {
+        group.bench_with_input(BenchmarkId::new("index only", k), &k, |b, &k| {
+            b.iter(|| {
+                black_box(collection.query_documents_with_options(
+                    &["query"],
+                    json!({}),
+                    k,
+                    &index_only,
+                ))
+                .unwrap();
+            })
+        });
+
+        group.bench_with_input(BenchmarkId::new("batched", k), &k, |b, &k| {
+            b.iter(|| {
+                black_box(collection.query_documents(&["query"], json!({}), k)).unwrap();
+            })
+        });
+
+        group.bench_with_input(BenchmarkId::new("per result", k), &k, |b, &k| {
+            b.iter(|| {
+                let res = collection
+                    .query_documents_with_options(&["query"], json!({}), k, &index_only)
+                    .unwrap();
+                for r in &res[0] {
+                    black_box(collection.get(&[r.uuid])).unwrap();
+                }
+            })
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, sentence_transformer_benchmark, query_benchmark);
 criterion_main!(benches);
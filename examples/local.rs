@@ -40,7 +40,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     let res = collection.query_documents(&["what is the best fruit?"], json!({}), 1)?;
 
     println!("text: {:?}", res[0][0].text);
-    println!("score: {:?}", 1.0 - res[0][0].distance);
+    println!("score: {:?}", res[0][0].distance.map(|d| 1.0 - d));
 
     Ok(())
 }
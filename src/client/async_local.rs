@@ -0,0 +1,455 @@
+use std::{collections::HashMap, sync::Arc};
+
+use async_trait::async_trait;
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::{
+    collection::AsyncCollection,
+    db::{
+        model::{validate_user_metadata, EmbeddingModel},
+        CollectionModel, Db, DbError,
+    },
+    embeddings::AsyncEmbeddingFunction,
+    filter::Filter,
+    index::IndexConfig,
+    Document, Embedding, QueryOptions, QueryResult,
+};
+
+use super::{
+    local::{check_embedding_model, embedded_documents, kept_embeddings},
+    AsyncClient, ClientError,
+};
+
+/// Async client over a local [`Db`]. Database calls run on tokio's blocking thread pool, so
+/// it must be used from within a tokio runtime.
+#[derive(Debug)]
+pub struct AsyncLocalClient<D: Db, E: AsyncEmbeddingFunction> {
+    db: Arc<D>,
+    embedding_fn: Arc<E>,
+}
+
+impl<D: Db, E: AsyncEmbeddingFunction> Clone for AsyncLocalClient<D, E> {
+    fn clone(&self) -> Self {
+        Self {
+            db: self.db.clone(),
+            embedding_fn: self.embedding_fn.clone(),
+        }
+    }
+}
+
+impl<D, E> AsyncLocalClient<D, E>
+where
+    D: Db,
+    E: AsyncEmbeddingFunction,
+{
+    pub fn init(db: D, embedding_fn: E) -> Result<Self, ClientError> {
+        Ok(Self {
+            db: Arc::new(db),
+            embedding_fn: Arc::new(embedding_fn),
+        })
+    }
+}
+
+impl<D, E> AsyncLocalClient<D, E>
+where
+    D: Db + 'static,
+    E: AsyncEmbeddingFunction,
+{
+    // Runs `f` on the blocking thread pool.
+    async fn with_db<T, F>(&self, f: F) -> Result<T, ClientError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&D) -> Result<T, DbError> + Send + 'static,
+    {
+        let db = self.db.clone();
+
+        Ok(tokio::task::spawn_blocking(move || f(&db)).await??)
+    }
+
+    async fn embed_documents(
+        &self,
+        docs: &[&Document],
+    ) -> Result<Vec<EmbeddingModel>, ClientError> {
+        let texts: Vec<_> = docs.iter().map(|doc| doc.text()).collect();
+
+        Ok(self
+            .embedding_fn
+            .embed(&texts)
+            .await?
+            .into_iter()
+            .zip(docs)
+            .map(|(e, doc)| EmbeddingModel {
+                embedding: e.into(),
+                uuid: doc.id(),
+                metadata: doc.metadata().clone(),
+                text: doc.text().to_string(),
+            })
+            .collect())
+    }
+
+    // See `LocalClient::record_embedding_model`.
+    async fn record_embedding_model(&self, collection_uuid: Uuid) -> Result<(), ClientError> {
+        let Some(model) = self.embedding_fn.model() else {
+            return Ok(());
+        };
+
+        let owned = model.to_string();
+        let recorded = self
+            .with_db(move |db| db.set_embedding_model(collection_uuid, &owned))
+            .await?;
+
+        check_embedding_model(&recorded, model)
+    }
+
+    async fn validate_embedding_model(&self, collection_uuid: Uuid) -> Result<(), ClientError> {
+        let Some(model) = self.embedding_fn.model() else {
+            return Ok(());
+        };
+
+        let collection = self
+            .with_db(move |db| db.get_collection_by_uuid(collection_uuid))
+            .await?;
+
+        match collection.as_ref().and_then(|c| c.embedding_model()) {
+            Some(recorded) => check_embedding_model(recorded, model),
+            None => Ok(()),
+        }
+    }
+}
+
+#[async_trait]
+impl<D, E> AsyncClient for AsyncLocalClient<D, E>
+where
+    D: Db + 'static,
+    E: AsyncEmbeddingFunction + 'static,
+{
+    async fn create_collection(&self, name: &str) -> Result<AsyncCollection, ClientError> {
+        self.create_collection_with_config(name, IndexConfig::default())
+            .await
+    }
+
+    async fn create_collection_with_config(
+        &self,
+        name: &str,
+        config: IndexConfig,
+    ) -> Result<AsyncCollection, ClientError> {
+        self.create_collection_with_metadata(name, config, Value::Null)
+            .await
+    }
+
+    async fn create_collection_with_metadata(
+        &self,
+        name: &str,
+        config: IndexConfig,
+        metadata: Value,
+    ) -> Result<AsyncCollection, ClientError> {
+        let mut metadata = validate_user_metadata(metadata)?;
+        config.write_metadata(&mut metadata);
+
+        let name = name.to_string();
+        let model = self
+            .with_db(move |db| db.create_collection(&name, Value::Object(metadata)))
+            .await?;
+
+        Ok(collection_model_to_instance(self.clone(), model))
+    }
+
+    async fn get_collection(&self, name: &str) -> Result<Option<AsyncCollection>, ClientError> {
+        let name = name.to_string();
+
+        Ok(self
+            .with_db(move |db| db.get_collection(&name))
+            .await?
+            .map(|model| collection_model_to_instance(self.clone(), model)))
+    }
+
+    async fn list_collection_names(&self) -> Result<Vec<String>, ClientError> {
+        Ok(self
+            .with_db(|db| db.list_collections())
+            .await?
+            .into_iter()
+            .map(|c| c.name)
+            .collect())
+    }
+
+    async fn delete_collection(&self, name: &str) -> Result<(), ClientError> {
+        let owned = name.to_string();
+        let uuid = self
+            .with_db(move |db| db.get_collection_uuid_from_name(&owned))
+            .await?
+            .ok_or_else(|| ClientError::CollectionNotFoundError(name.to_string()))?;
+
+        self.with_db(move |db| db.delete_collection(uuid)).await
+    }
+
+    async fn get_collection_metadata(&self, collection_uuid: Uuid) -> Result<Value, ClientError> {
+        Ok(self
+            .with_db(move |db| db.get_collection_by_uuid(collection_uuid))
+            .await?
+            .ok_or(DbError::CollectionNotFoundError(collection_uuid))?
+            .user_metadata())
+    }
+
+    async fn update_collection_metadata(
+        &self,
+        collection_uuid: Uuid,
+        metadata: Value,
+    ) -> Result<(), ClientError> {
+        self.with_db(move |db| {
+            db.update_collection_metadata(collection_uuid, metadata)
+                .map(|_| ())
+        })
+        .await
+    }
+
+    async fn reset(&self) -> Result<(), ClientError> {
+        self.with_db(|db| db.reset()).await
+    }
+
+    async fn embed(&self, texts: &[&str]) -> Result<Vec<Embedding>, ClientError> {
+        Ok(self.embedding_fn.embed(texts).await?)
+    }
+
+    async fn add_documents(
+        &self,
+        collection_uuid: Uuid,
+        docs: &[Document],
+    ) -> Result<(), ClientError> {
+        self.record_embedding_model(collection_uuid).await?;
+        let embeddings = self
+            .embed_documents(&docs.iter().collect::<Vec<_>>())
+            .await?;
+
+        self.with_db(move |db| db.add_embeddings(collection_uuid, embeddings))
+            .await
+    }
+
+    async fn add_documents_with_embeddings(
+        &self,
+        collection_uuid: Uuid,
+        docs: &[(Document, Embedding)],
+    ) -> Result<(), ClientError> {
+        let embeddings = embedded_documents(docs);
+
+        self.with_db(move |db| db.add_embeddings(collection_uuid, embeddings))
+            .await
+    }
+
+    async fn upsert_documents(
+        &self,
+        collection_uuid: Uuid,
+        docs: &[Document],
+    ) -> Result<(), ClientError> {
+        let ids: Vec<_> = docs.iter().map(|doc| doc.id()).collect();
+        let existing: HashMap<_, _> = self
+            .with_db(move |db| db.get_embeddings_by_ids(collection_uuid, &ids))
+            .await?
+            .into_iter()
+            .map(|e| (e.uuid, e))
+            .collect();
+
+        // see `LocalClient::upsert_documents`
+        let (unchanged, changed): (Vec<_>, Vec<_>) = docs.iter().partition(|doc| {
+            existing
+                .get(&doc.id())
+                .map_or(false, |e| e.text == doc.text())
+        });
+
+        let mut embeddings = kept_embeddings(&unchanged, &existing);
+        if !changed.is_empty() {
+            self.record_embedding_model(collection_uuid).await?;
+            embeddings.extend(self.embed_documents(&changed).await?);
+        }
+
+        self.with_db(move |db| db.upsert_embeddings(collection_uuid, embeddings))
+            .await
+    }
+
+    async fn update_metadata(
+        &self,
+        collection_uuid: Uuid,
+        id: Uuid,
+        metadata: Value,
+    ) -> Result<(), ClientError> {
+        self.with_db(move |db| db.update_metadata(collection_uuid, id, metadata))
+            .await
+    }
+
+    async fn get_documents(
+        &self,
+        collection_uuid: Uuid,
+        ids: &[Uuid],
+    ) -> Result<Vec<Document>, ClientError> {
+        let ids = ids.to_vec();
+
+        Ok(self
+            .with_db(move |db| db.get_embeddings_by_ids(collection_uuid, &ids))
+            .await?
+            .into_iter()
+            .map(Into::into)
+            .collect())
+    }
+
+    async fn get_documents_page(
+        &self,
+        collection_uuid: Uuid,
+        filter: &Filter,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<Document>, ClientError> {
+        let filter = filter.clone();
+
+        Ok(self
+            .with_db(move |db| db.get_embeddings_page(collection_uuid, &filter, offset, limit))
+            .await?
+            .into_iter()
+            .map(Into::into)
+            .collect())
+    }
+
+    async fn delete_documents(
+        &self,
+        collection_uuid: Uuid,
+        ids: &[Uuid],
+    ) -> Result<usize, ClientError> {
+        let ids = ids.to_vec();
+
+        self.with_db(move |db| db.delete_embeddings(collection_uuid, &ids))
+            .await
+    }
+
+    async fn delete_documents_where(
+        &self,
+        collection_uuid: Uuid,
+        filter: &Filter,
+    ) -> Result<usize, ClientError> {
+        let filter = filter.clone();
+
+        self.with_db(move |db| db.delete_embeddings_where(collection_uuid, &filter))
+            .await
+    }
+
+    async fn query(
+        &self,
+        collection_uuid: Uuid,
+        queries: &[&str],
+        filter: &Filter,
+        k: usize,
+        options: &QueryOptions,
+    ) -> Result<Vec<Vec<QueryResult>>, ClientError> {
+        self.validate_embedding_model(collection_uuid).await?;
+        let embeddings = self.embed(queries).await?;
+
+        self.query_embeddings(collection_uuid, &embeddings, filter, k, options)
+            .await
+    }
+
+    async fn query_embeddings(
+        &self,
+        collection_uuid: Uuid,
+        embeddings: &[Embedding],
+        filter: &Filter,
+        k: usize,
+        options: &QueryOptions,
+    ) -> Result<Vec<Vec<QueryResult>>, ClientError> {
+        let embeddings = embeddings.to_vec();
+        let filter = filter.clone();
+        let options = options.clone();
+
+        self.with_db(move |db| db.query(collection_uuid, &embeddings, &filter, k, &options))
+            .await
+    }
+
+    async fn persist(&self) -> Result<(), ClientError> {
+        self.with_db(|db| db.persist()).await
+    }
+}
+
+fn collection_model_to_instance<D: Db + 'static, E: AsyncEmbeddingFunction + 'static>(
+    client: AsyncLocalClient<D, E>,
+    model: CollectionModel,
+) -> AsyncCollection {
+    AsyncCollection {
+        client: Box::new(client),
+        uuid: model.uuid,
+        name: model.name,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use crate::{
+        client::{async_local::AsyncLocalClient, AsyncClient},
+        db::{duckdb::DuckDB, Db},
+        embeddings::{sentencetransformer::SentenceTransformerEmbeddings, BlockingEmbeddings},
+        Document,
+    };
+
+    #[tokio::test]
+    pub async fn test_async_collection() {
+        let db = DuckDB::new(Default::default()).unwrap();
+        db.init().unwrap();
+
+        let embedding_fn = BlockingEmbeddings::new(SentenceTransformerEmbeddings::new());
+
+        let client = AsyncLocalClient::init(db, embedding_fn).unwrap();
+
+        let mut collection = client.create_collection("collection1").await.unwrap();
+        assert_eq!(
+            client.list_collection_names().await.unwrap(),
+            vec!["collection1".to_string()]
+        );
+
+        let docs = vec![
+            Document::new("hello world!".to_string(), json!({"source": "notion"})),
+            Document::new("goodbye world!".to_string(), json!({"source": "slack"})),
+        ];
+        collection.add_documents(&docs).await.unwrap();
+
+        let res = collection
+            .query_documents(&["hello"], json!({ "source": "notion" }), 1)
+            .await
+            .unwrap();
+        assert_eq!(res[0][0].text.as_ref(), Some(&docs[0].text));
+
+        assert_eq!(collection.delete(&[docs[0].id]).await.unwrap(), 1);
+        assert_eq!(collection.peek(10).await.unwrap().len(), 1);
+
+        client.delete_collection("collection1").await.unwrap();
+        assert!(client
+            .get_collection("collection1")
+            .await
+            .unwrap()
+            .is_none());
+        assert!(client.delete_collection("collection1").await.is_err());
+    }
+
+    #[tokio::test]
+    pub async fn test_async_collection_documents() {
+        let db = DuckDB::new(Default::default()).unwrap();
+        db.init().unwrap();
+
+        let embedding_fn = BlockingEmbeddings::new(SentenceTransformerEmbeddings::new());
+
+        let client = AsyncLocalClient::init(db, embedding_fn).unwrap();
+
+        let mut collection = client.create_collection("collection1").await.unwrap();
+
+        let docs: Vec<_> = (0..5)
+            .map(|i| Document::new(format!("document {i}"), json!({ "i": i })))
+            .collect();
+        collection.add_documents(&docs).await.unwrap();
+
+        let mut documents = collection
+            .documents(json!({ "i": { "$gte": 1 } }), 2)
+            .unwrap();
+        let mut ids = vec![];
+        while let Some(doc) = documents.next_document().await {
+            ids.push(doc.unwrap().id);
+        }
+        assert_eq!(ids, docs[1..].iter().map(|d| d.id).collect::<Vec<_>>());
+    }
+}
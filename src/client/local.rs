@@ -1,13 +1,18 @@
-use std::sync::Arc;
+use std::{collections::HashMap, sync::Arc};
 
 use serde_json::Value;
 use uuid::Uuid;
 
 use crate::{
     collection::Collection,
-    db::{model::EmbeddingModel, CollectionModel, Db},
+    db::{
+        model::{validate_user_metadata, EmbeddingModel},
+        CollectionModel, Db, DbError,
+    },
     embeddings::EmbeddingFunction,
-    Document, Embedding, QueryResult,
+    filter::Filter,
+    index::IndexConfig,
+    Document, Embedding, QueryOptions, QueryResult,
 };
 
 use super::{Client, ClientError};
@@ -40,6 +45,55 @@ where
     }
 }
 
+impl<D, E> LocalClient<D, E>
+where
+    D: Db,
+    E: EmbeddingFunction,
+{
+    fn embed_documents<'a>(
+        &self,
+        docs: impl IntoIterator<Item = &'a Document> + Clone,
+    ) -> Result<Vec<EmbeddingModel>, ClientError> {
+        Ok(self
+            .embedding_fn
+            .embed(docs.clone().into_iter().map(|doc| doc.text()))?
+            .into_iter()
+            .zip(docs)
+            .map(|(e, doc)| EmbeddingModel {
+                embedding: e.into(),
+                uuid: doc.id(),
+                metadata: doc.metadata().clone(),
+                text: doc.text().to_string(),
+            })
+            .collect())
+    }
+
+    // Records the model of the embedding function on the collection, unless another model is
+    // recorded already.
+    fn record_embedding_model(&self, collection_uuid: Uuid) -> Result<(), ClientError> {
+        match self.embedding_fn.model() {
+            Some(model) => {
+                let recorded = self.db.set_embedding_model(collection_uuid, model)?;
+                check_embedding_model(&recorded, model)
+            }
+            None => Ok(()),
+        }
+    }
+
+    fn validate_embedding_model(&self, collection_uuid: Uuid) -> Result<(), ClientError> {
+        let Some(model) = self.embedding_fn.model() else {
+            return Ok(());
+        };
+
+        let collection = self.db.get_collection_by_uuid(collection_uuid)?;
+
+        match collection.as_ref().and_then(|c| c.embedding_model()) {
+            Some(recorded) => check_embedding_model(recorded, model),
+            None => Ok(()),
+        }
+    }
+}
+
 impl<D, E> Client for LocalClient<D, E>
 where
     D: Db + 'static,
@@ -47,7 +101,27 @@ where
     LocalClient<D, E>: Clone,
 {
     fn create_collection(&mut self, name: &str) -> Result<Collection, ClientError> {
-        let model = self.db.create_collection(name)?;
+        self.create_collection_with_config(name, IndexConfig::default())
+    }
+
+    fn create_collection_with_config(
+        &mut self,
+        name: &str,
+        config: IndexConfig,
+    ) -> Result<Collection, ClientError> {
+        self.create_collection_with_metadata(name, config, Value::Null)
+    }
+
+    fn create_collection_with_metadata(
+        &mut self,
+        name: &str,
+        config: IndexConfig,
+        metadata: Value,
+    ) -> Result<Collection, ClientError> {
+        let mut metadata = validate_user_metadata(metadata)?;
+        config.write_metadata(&mut metadata);
+
+        let model = self.db.create_collection(name, Value::Object(metadata))?;
 
         Ok(collection_model_to_instance(self.clone(), model))
     }
@@ -68,42 +142,209 @@ where
             .collect())
     }
 
+    fn delete_collection(&mut self, name: &str) -> Result<(), ClientError> {
+        let uuid = self
+            .db
+            .get_collection_uuid_from_name(name)?
+            .ok_or_else(|| ClientError::CollectionNotFoundError(name.to_string()))?;
+
+        Ok(self.db.delete_collection(uuid)?)
+    }
+
+    fn get_collection_metadata(&self, collection_uuid: Uuid) -> Result<Value, ClientError> {
+        Ok(self
+            .db
+            .get_collection_by_uuid(collection_uuid)?
+            .ok_or(DbError::CollectionNotFoundError(collection_uuid))?
+            .user_metadata())
+    }
+
+    fn update_collection_metadata(
+        &self,
+        collection_uuid: Uuid,
+        metadata: Value,
+    ) -> Result<(), ClientError> {
+        self.db
+            .update_collection_metadata(collection_uuid, metadata)?;
+
+        Ok(())
+    }
+
+    fn reset(&mut self) -> Result<(), ClientError> {
+        Ok(self.db.reset()?)
+    }
+
     fn embed(&self, texts: &[&str]) -> Result<Vec<Embedding>, ClientError> {
         Ok(self.embedding_fn.embed(texts)?)
     }
 
     fn add_documents(&self, collection_uuid: Uuid, docs: &[Document]) -> Result<(), ClientError> {
-        let embeddings = self
-            .embedding_fn
-            .embed(docs.iter().map(|doc| doc.text()))?
-            .into_iter()
-            .zip(docs.iter())
-            .map(|(e, doc)| EmbeddingModel {
-                embedding: e.into(),
-                uuid: doc.id(),
-                metadata: doc.metadata().clone(),
-                text: doc.text().to_string(),
-            })
-            .collect();
+        self.record_embedding_model(collection_uuid)?;
+        let embeddings = self.embed_documents(docs)?;
 
         self.db.add_embeddings(collection_uuid, embeddings)?;
 
         Ok(())
     }
 
+    fn add_documents_with_embeddings(
+        &self,
+        collection_uuid: Uuid,
+        docs: &[(Document, Embedding)],
+    ) -> Result<(), ClientError> {
+        self.db
+            .add_embeddings(collection_uuid, embedded_documents(docs))?;
+
+        Ok(())
+    }
+
+    fn upsert_documents(
+        &self,
+        collection_uuid: Uuid,
+        docs: &[Document],
+    ) -> Result<(), ClientError> {
+        let ids: Vec<_> = docs.iter().map(|doc| doc.id()).collect();
+        let existing: HashMap<_, _> = self
+            .db
+            .get_embeddings_by_ids(collection_uuid, &ids)?
+            .into_iter()
+            .map(|e| (e.uuid, e))
+            .collect();
+
+        // documents whose text did not change keep their vector, only the metadata is replaced
+        let (unchanged, changed): (Vec<_>, Vec<_>) = docs.iter().partition(|doc| {
+            existing
+                .get(&doc.id())
+                .map_or(false, |e| e.text == doc.text())
+        });
+
+        let mut embeddings = kept_embeddings(&unchanged, &existing);
+        if !changed.is_empty() {
+            self.record_embedding_model(collection_uuid)?;
+            embeddings.extend(self.embed_documents(changed)?);
+        }
+
+        Ok(self.db.upsert_embeddings(collection_uuid, embeddings)?)
+    }
+
+    fn update_metadata(
+        &self,
+        collection_uuid: Uuid,
+        id: Uuid,
+        metadata: Value,
+    ) -> Result<(), ClientError> {
+        Ok(self.db.update_metadata(collection_uuid, id, metadata)?)
+    }
+
+    fn get_documents(
+        &self,
+        collection_uuid: Uuid,
+        ids: &[Uuid],
+    ) -> Result<Vec<Document>, ClientError> {
+        Ok(self
+            .db
+            .get_embeddings_by_ids(collection_uuid, ids)?
+            .into_iter()
+            .map(Into::into)
+            .collect())
+    }
+
+    fn get_documents_page(
+        &self,
+        collection_uuid: Uuid,
+        filter: &Filter,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<Document>, ClientError> {
+        Ok(self
+            .db
+            .get_embeddings_page(collection_uuid, filter, offset, limit)?
+            .into_iter()
+            .map(Into::into)
+            .collect())
+    }
+
+    fn delete_documents(&self, collection_uuid: Uuid, ids: &[Uuid]) -> Result<usize, ClientError> {
+        Ok(self.db.delete_embeddings(collection_uuid, ids)?)
+    }
+
+    fn delete_documents_where(
+        &self,
+        collection_uuid: Uuid,
+        filter: &Filter,
+    ) -> Result<usize, ClientError> {
+        Ok(self.db.delete_embeddings_where(collection_uuid, filter)?)
+    }
+
     fn query(
         &self,
         collection_uuid: Uuid,
         queries: &[&str],
-        _where: Value,
+        filter: &Filter,
         k: usize,
+        options: &QueryOptions,
     ) -> Result<Vec<Vec<QueryResult>>, ClientError> {
+        self.validate_embedding_model(collection_uuid)?;
         let embeddings = self.embed(queries)?;
 
-        Ok(self.db.query(collection_uuid, &embeddings, _where, k)?)
+        self.query_embeddings(collection_uuid, &embeddings, filter, k, options)
+    }
+
+    fn query_embeddings(
+        &self,
+        collection_uuid: Uuid,
+        embeddings: &[Embedding],
+        filter: &Filter,
+        k: usize,
+        options: &QueryOptions,
+    ) -> Result<Vec<Vec<QueryResult>>, ClientError> {
+        Ok(self
+            .db
+            .query(collection_uuid, embeddings, filter, k, options)?)
+    }
+
+    fn persist(&self) -> Result<(), ClientError> {
+        Ok(self.db.persist()?)
+    }
+}
+
+pub(super) fn check_embedding_model(recorded: &str, model: &str) -> Result<(), ClientError> {
+    if recorded == model {
+        Ok(())
+    } else {
+        Err(ClientError::ModelMismatchError {
+            expected: recorded.to_string(),
+            found: model.to_string(),
+        })
     }
 }
 
+// Rows of `docs` with the vectors they already have in `existing`.
+pub(super) fn kept_embeddings(
+    docs: &[&Document],
+    existing: &HashMap<Uuid, EmbeddingModel>,
+) -> Vec<EmbeddingModel> {
+    docs.iter()
+        .map(|doc| EmbeddingModel {
+            embedding: existing[&doc.id()].embedding.clone(),
+            uuid: doc.id(),
+            metadata: doc.metadata().clone(),
+            text: doc.text().to_string(),
+        })
+        .collect()
+}
+
+pub(super) fn embedded_documents(docs: &[(Document, Embedding)]) -> Vec<EmbeddingModel> {
+    docs.iter()
+        .map(|(doc, e)| EmbeddingModel {
+            embedding: e.get().clone(),
+            uuid: doc.id(),
+            metadata: doc.metadata().clone(),
+            text: doc.text().to_string(),
+        })
+        .collect()
+}
+
 fn collection_model_to_instance<D: Db + 'static, E: EmbeddingFunction + 'static>(
     client: LocalClient<D, E>,
     model: CollectionModel,
@@ -142,4 +383,24 @@ mod test {
         assert_eq!(collection.name, collection1.name);
         assert_eq!(collection.uuid, collection1.uuid);
     }
+
+    #[test]
+    pub fn test_delete_collection_local() {
+        let db = DuckDB::new(Default::default()).unwrap();
+        db.init().unwrap();
+
+        let embedding_fn = SentenceTransformerEmbeddings::new();
+
+        let mut client = LocalClient::init(db, embedding_fn).unwrap();
+
+        client.create_collection("collection1").unwrap();
+        client.create_collection("collection2").unwrap();
+
+        client.delete_collection("collection1").unwrap();
+        assert_eq!(client.list_collection_names().unwrap(), vec!["collection2"]);
+        assert!(client.delete_collection("collection1").is_err());
+
+        client.reset().unwrap();
+        assert!(client.list_collection_names().unwrap().is_empty());
+    }
 }
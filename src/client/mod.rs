@@ -1,43 +1,247 @@
+use async_trait::async_trait;
 use serde_json::Value;
 use uuid::Uuid;
 
 use crate::{
-    collection::Collection, db::DbError, embeddings::EmbeddingError, Document, Embedding,
-    QueryResult,
+    collection::{AsyncCollection, Collection},
+    db::DbError,
+    embeddings::EmbeddingError,
+    filter::Filter,
+    index::IndexConfig,
+    Document, Embedding, QueryOptions, QueryResult,
 };
 
+pub mod async_local;
 pub mod local;
 
+pub use async_local::AsyncLocalClient;
 pub use local::LocalClient;
 
-pub trait Client {
+pub trait Client: Send + Sync {
     fn create_collection(&mut self, name: &str) -> Result<Collection, ClientError>;
 
+    fn create_collection_with_config(
+        &mut self,
+        name: &str,
+        config: IndexConfig,
+    ) -> Result<Collection, ClientError>;
+
+    /// Creates a collection tagged with `metadata`, which must be a JSON object. Keys starting
+    /// with `index:`, `hnsw:`, `ivf:`, `pq:` or `embedding:` are reserved.
+    fn create_collection_with_metadata(
+        &mut self,
+        name: &str,
+        config: IndexConfig,
+        metadata: Value,
+    ) -> Result<Collection, ClientError>;
+
     fn get_collection(&self, name: &str) -> Result<Option<Collection>, ClientError>;
 
     fn list_collection_names(&self) -> Result<Vec<String>, ClientError>;
 
+    fn delete_collection(&mut self, name: &str) -> Result<(), ClientError>;
+
+    fn get_collection_metadata(&self, collection_uuid: Uuid) -> Result<Value, ClientError>;
+
+    fn update_collection_metadata(
+        &self,
+        collection_uuid: Uuid,
+        metadata: Value,
+    ) -> Result<(), ClientError>;
+
+    fn reset(&mut self) -> Result<(), ClientError>;
+
     fn embed(&self, texts: &[&str]) -> Result<Vec<Embedding>, ClientError>;
 
     fn add_documents(&self, collection_uuid: Uuid, docs: &[Document]) -> Result<(), ClientError>;
 
+    /// Adds documents with embeddings that were computed ahead of time.
+    fn add_documents_with_embeddings(
+        &self,
+        collection_uuid: Uuid,
+        docs: &[(Document, Embedding)],
+    ) -> Result<(), ClientError>;
+
+    fn upsert_documents(&self, collection_uuid: Uuid, docs: &[Document])
+        -> Result<(), ClientError>;
+
+    fn update_metadata(
+        &self,
+        collection_uuid: Uuid,
+        id: Uuid,
+        metadata: Value,
+    ) -> Result<(), ClientError>;
+
+    fn get_documents(
+        &self,
+        collection_uuid: Uuid,
+        ids: &[Uuid],
+    ) -> Result<Vec<Document>, ClientError>;
+
+    fn get_documents_page(
+        &self,
+        collection_uuid: Uuid,
+        filter: &Filter,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<Document>, ClientError>;
+
+    fn delete_documents(&self, collection_uuid: Uuid, ids: &[Uuid]) -> Result<usize, ClientError>;
+
+    fn delete_documents_where(
+        &self,
+        collection_uuid: Uuid,
+        filter: &Filter,
+    ) -> Result<usize, ClientError>;
+
     fn query(
         &self,
         collection_uuid: Uuid,
         queries: &[&str],
-        _where: Value,
+        filter: &Filter,
         k: usize,
+        options: &QueryOptions,
     ) -> Result<Vec<Vec<QueryResult>>, ClientError>;
+
+    /// Like [`Client::query`], with precomputed query embeddings.
+    fn query_embeddings(
+        &self,
+        collection_uuid: Uuid,
+        embeddings: &[Embedding],
+        filter: &Filter,
+        k: usize,
+        options: &QueryOptions,
+    ) -> Result<Vec<Vec<QueryResult>>, ClientError>;
+
+    fn persist(&self) -> Result<(), ClientError>;
+}
+
+/// Async counterpart of [`Client`]. Embedding and database work never block the calling task.
+#[async_trait]
+pub trait AsyncClient: Send + Sync {
+    async fn create_collection(&self, name: &str) -> Result<AsyncCollection, ClientError>;
+
+    async fn create_collection_with_config(
+        &self,
+        name: &str,
+        config: IndexConfig,
+    ) -> Result<AsyncCollection, ClientError>;
+
+    /// See [`Client::create_collection_with_metadata`].
+    async fn create_collection_with_metadata(
+        &self,
+        name: &str,
+        config: IndexConfig,
+        metadata: Value,
+    ) -> Result<AsyncCollection, ClientError>;
+
+    async fn get_collection(&self, name: &str) -> Result<Option<AsyncCollection>, ClientError>;
+
+    async fn list_collection_names(&self) -> Result<Vec<String>, ClientError>;
+
+    async fn delete_collection(&self, name: &str) -> Result<(), ClientError>;
+
+    async fn get_collection_metadata(&self, collection_uuid: Uuid) -> Result<Value, ClientError>;
+
+    async fn update_collection_metadata(
+        &self,
+        collection_uuid: Uuid,
+        metadata: Value,
+    ) -> Result<(), ClientError>;
+
+    async fn reset(&self) -> Result<(), ClientError>;
+
+    async fn embed(&self, texts: &[&str]) -> Result<Vec<Embedding>, ClientError>;
+
+    async fn add_documents(
+        &self,
+        collection_uuid: Uuid,
+        docs: &[Document],
+    ) -> Result<(), ClientError>;
+
+    async fn add_documents_with_embeddings(
+        &self,
+        collection_uuid: Uuid,
+        docs: &[(Document, Embedding)],
+    ) -> Result<(), ClientError>;
+
+    async fn upsert_documents(
+        &self,
+        collection_uuid: Uuid,
+        docs: &[Document],
+    ) -> Result<(), ClientError>;
+
+    async fn update_metadata(
+        &self,
+        collection_uuid: Uuid,
+        id: Uuid,
+        metadata: Value,
+    ) -> Result<(), ClientError>;
+
+    async fn get_documents(
+        &self,
+        collection_uuid: Uuid,
+        ids: &[Uuid],
+    ) -> Result<Vec<Document>, ClientError>;
+
+    async fn get_documents_page(
+        &self,
+        collection_uuid: Uuid,
+        filter: &Filter,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<Document>, ClientError>;
+
+    async fn delete_documents(
+        &self,
+        collection_uuid: Uuid,
+        ids: &[Uuid],
+    ) -> Result<usize, ClientError>;
+
+    async fn delete_documents_where(
+        &self,
+        collection_uuid: Uuid,
+        filter: &Filter,
+    ) -> Result<usize, ClientError>;
+
+    async fn query(
+        &self,
+        collection_uuid: Uuid,
+        queries: &[&str],
+        filter: &Filter,
+        k: usize,
+        options: &QueryOptions,
+    ) -> Result<Vec<Vec<QueryResult>>, ClientError>;
+
+    async fn query_embeddings(
+        &self,
+        collection_uuid: Uuid,
+        embeddings: &[Embedding],
+        filter: &Filter,
+        k: usize,
+        options: &QueryOptions,
+    ) -> Result<Vec<Vec<QueryResult>>, ClientError>;
+
+    async fn persist(&self) -> Result<(), ClientError>;
 }
 
 #[derive(thiserror::Error, Debug)]
 pub enum ClientError {
     #[error("Failed to initialise Cedar Client: {0}")]
-    InitError(Box<dyn std::error::Error>),
+    InitError(Box<dyn std::error::Error + Send + Sync>),
 
     #[error("Failed to perform db operation: {0}")]
     DbError(#[from] DbError),
 
     #[error("Embedding function failed to embed texts: {0}")]
     EmbeddingFnError(#[from] EmbeddingError),
+
+    #[error("Collection {0} does not exist")]
+    CollectionNotFoundError(String),
+
+    #[error("Collection holds embeddings of model {expected}, found {found}")]
+    ModelMismatchError { expected: String, found: String },
+
+    #[error("Background task failed: {0}")]
+    TaskError(#[from] tokio::task::JoinError),
 }
@@ -0,0 +1,289 @@
+use std::{
+    collections::VecDeque,
+    fmt::{Debug, Display},
+};
+
+use uuid::Uuid;
+
+use crate::{client::AsyncClient, filter::Filter, Document, Embedding, QueryOptions, QueryResult};
+
+use super::{validate_documents, validate_embeddings, CollectionError};
+
+/// Async counterpart of [`super::Collection`], returned by an [`AsyncClient`].
+pub struct AsyncCollection {
+    pub(crate) client: Box<dyn AsyncClient>,
+    pub(crate) uuid: uuid::Uuid,
+    pub(crate) name: String,
+}
+
+impl Debug for AsyncCollection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("collection")
+            .field("uuid", &self.uuid)
+            .field("name", &self.name)
+            .finish()
+    }
+}
+
+impl Display for AsyncCollection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("collection")
+            .field("uuid", &self.uuid)
+            .field("name", &self.name)
+            .finish()
+    }
+}
+
+impl AsyncCollection {
+    pub async fn add_documents(&mut self, documents: &[Document]) -> Result<(), CollectionError> {
+        validate_documents(documents)?;
+
+        self.client.add_documents(self.uuid, documents).await?;
+
+        Ok(())
+    }
+
+    /// See [`super::Collection::add_documents_with_embeddings`].
+    pub async fn add_documents_with_embeddings(
+        &mut self,
+        documents: &[(Document, Embedding)],
+    ) -> Result<(), CollectionError> {
+        validate_documents(documents.iter().map(|(doc, _)| doc))?;
+        validate_embeddings(documents.iter().map(|(_, embedding)| embedding))?;
+
+        self.client
+            .add_documents_with_embeddings(self.uuid, documents)
+            .await?;
+
+        Ok(())
+    }
+
+    /// See [`super::Collection::upsert_documents`].
+    pub async fn upsert_documents(
+        &mut self,
+        documents: &[Document],
+    ) -> Result<(), CollectionError> {
+        validate_documents(documents)?;
+
+        self.client.upsert_documents(self.uuid, documents).await?;
+
+        Ok(())
+    }
+
+    /// Replaces the metadata of a stored document without touching its embedding.
+    pub async fn update_metadata(
+        &mut self,
+        id: Uuid,
+        metadata: serde_json::Value,
+    ) -> Result<(), CollectionError> {
+        Ok(self.client.update_metadata(self.uuid, id, metadata).await?)
+    }
+
+    /// See [`super::Collection::metadata`].
+    pub async fn metadata(&self) -> Result<serde_json::Value, CollectionError> {
+        Ok(self.client.get_collection_metadata(self.uuid).await?)
+    }
+
+    /// See [`super::Collection::set_metadata`].
+    pub async fn set_metadata(
+        &mut self,
+        metadata: serde_json::Value,
+    ) -> Result<(), CollectionError> {
+        Ok(self
+            .client
+            .update_collection_metadata(self.uuid, metadata)
+            .await?)
+    }
+
+    /// Fetches the stored documents with the given ids. Unknown ids are skipped.
+    pub async fn get(&self, ids: &[Uuid]) -> Result<Vec<Document>, CollectionError> {
+        Ok(self.client.get_documents(self.uuid, ids).await?)
+    }
+
+    /// Returns the first `n` documents of the collection.
+    pub async fn peek(&self, n: usize) -> Result<Vec<Document>, CollectionError> {
+        Ok(self
+            .client
+            .get_documents_page(self.uuid, &Filter::All, 0, n)
+            .await?)
+    }
+
+    /// Returns up to `limit` documents matching `filter`, skipping the first `offset`.
+    pub async fn get_where<F>(
+        &self,
+        filter: F,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<Document>, CollectionError>
+    where
+        F: TryInto<Filter>,
+        CollectionError: From<F::Error>,
+    {
+        let filter = filter.try_into()?;
+
+        Ok(self
+            .client
+            .get_documents_page(self.uuid, &filter, offset, limit)
+            .await?)
+    }
+
+    /// See [`super::Collection::documents`]. Documents are read with
+    /// [`AsyncDocuments::next_document`].
+    pub fn documents<F>(
+        &self,
+        filter: F,
+        page_size: usize,
+    ) -> Result<AsyncDocuments<'_>, CollectionError>
+    where
+        F: TryInto<Filter>,
+        CollectionError: From<F::Error>,
+    {
+        Ok(AsyncDocuments {
+            collection: self,
+            filter: filter.try_into()?,
+            offset: 0,
+            page_size: page_size.max(1),
+            page: VecDeque::new(),
+            done: false,
+        })
+    }
+
+    /// Removes the documents with the given ids. Returns the number of removed documents.
+    pub async fn delete(&mut self, ids: &[Uuid]) -> Result<usize, CollectionError> {
+        Ok(self.client.delete_documents(self.uuid, ids).await?)
+    }
+
+    /// Removes all documents matching `filter`. Returns the number of removed documents.
+    pub async fn delete_where<F>(&mut self, filter: F) -> Result<usize, CollectionError>
+    where
+        F: TryInto<Filter>,
+        CollectionError: From<F::Error>,
+    {
+        let filter = filter.try_into()?;
+
+        Ok(self
+            .client
+            .delete_documents_where(self.uuid, &filter)
+            .await?)
+    }
+
+    /// See [`super::Collection::query_documents`].
+    pub async fn query_documents<F>(
+        &self,
+        queries: &[&str],
+        filter: F,
+        k: usize,
+    ) -> Result<Vec<Vec<QueryResult>>, CollectionError>
+    where
+        F: TryInto<Filter>,
+        CollectionError: From<F::Error>,
+    {
+        self.query_documents_with_options(queries, filter, k, &QueryOptions::default())
+            .await
+    }
+
+    /// Like [`AsyncCollection::query_documents`], with per-query search parameters.
+    pub async fn query_documents_with_options<F>(
+        &self,
+        queries: &[&str],
+        filter: F,
+        k: usize,
+        options: &QueryOptions,
+    ) -> Result<Vec<Vec<QueryResult>>, CollectionError>
+    where
+        F: TryInto<Filter>,
+        CollectionError: From<F::Error>,
+    {
+        let filter = filter.try_into()?;
+
+        Ok(self
+            .client
+            .query(self.uuid, queries, &filter, k, options)
+            .await?)
+    }
+
+    /// See [`super::Collection::query_embeddings`].
+    pub async fn query_embeddings<F>(
+        &self,
+        embeddings: &[Embedding],
+        filter: F,
+        k: usize,
+    ) -> Result<Vec<Vec<QueryResult>>, CollectionError>
+    where
+        F: TryInto<Filter>,
+        CollectionError: From<F::Error>,
+    {
+        self.query_embeddings_with_options(embeddings, filter, k, &QueryOptions::default())
+            .await
+    }
+
+    /// Like [`AsyncCollection::query_embeddings`], with per-query search parameters.
+    pub async fn query_embeddings_with_options<F>(
+        &self,
+        embeddings: &[Embedding],
+        filter: F,
+        k: usize,
+        options: &QueryOptions,
+    ) -> Result<Vec<Vec<QueryResult>>, CollectionError>
+    where
+        F: TryInto<Filter>,
+        CollectionError: From<F::Error>,
+    {
+        validate_embeddings(embeddings)?;
+        let filter = filter.try_into()?;
+
+        Ok(self
+            .client
+            .query_embeddings(self.uuid, embeddings, &filter, k, options)
+            .await?)
+    }
+}
+
+/// Async counterpart of [`super::Documents`], see [`AsyncCollection::documents`].
+pub struct AsyncDocuments<'a> {
+    collection: &'a AsyncCollection,
+    filter: Filter,
+    offset: usize,
+    page_size: usize,
+    page: VecDeque<Document>,
+    done: bool,
+}
+
+impl AsyncDocuments<'_> {
+    /// Starts the iteration at the `offset`-th matching document.
+    pub fn skip_to(mut self, offset: usize) -> Self {
+        self.offset = offset;
+        self
+    }
+
+    /// Returns the next document, fetching the next page when the current one is used up.
+    /// Returns `None` once all documents have been returned or after an error.
+    pub async fn next_document(&mut self) -> Option<Result<Document, CollectionError>> {
+        if self.page.is_empty() && !self.done {
+            let page = self
+                .collection
+                .client
+                .get_documents_page(
+                    self.collection.uuid,
+                    &self.filter,
+                    self.offset,
+                    self.page_size,
+                )
+                .await;
+
+            match page {
+                Ok(page) => {
+                    self.done = page.len() < self.page_size;
+                    self.offset += page.len();
+                    self.page.extend(page);
+                }
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e.into()));
+                }
+            }
+        }
+
+        self.page.pop_front().map(Ok)
+    }
+}
@@ -1,13 +1,20 @@
 use std::{
-    collections::HashSet,
+    collections::{HashSet, VecDeque},
+    convert::Infallible,
     fmt::{Debug, Display},
     hash::Hash,
 };
 
 use crate::{
     client::{Client, ClientError},
-    Document, QueryResult,
+    filter::{Filter, FilterError},
+    Document, Embedding, QueryOptions, QueryResult,
 };
+use uuid::Uuid;
+
+mod async_collection;
+
+pub use async_collection::{AsyncCollection, AsyncDocuments};
 
 pub struct Collection {
     pub(crate) client: Box<dyn Client>,
@@ -42,22 +49,253 @@ impl Collection {
         Ok(())
     }
 
-    pub fn query_documents(
+    /// Adds documents together with embeddings computed ahead of time, without running the
+    /// embedding function. All embeddings must have the dimension of the collection.
+    pub fn add_documents_with_embeddings(
+        &mut self,
+        documents: &[(Document, Embedding)],
+    ) -> Result<(), CollectionError> {
+        validate_documents(documents.iter().map(|(doc, _)| doc))?;
+        validate_embeddings(documents.iter().map(|(_, embedding)| embedding))?;
+
+        self.client
+            .add_documents_with_embeddings(self.uuid, documents)?;
+
+        Ok(())
+    }
+
+    /// Inserts the given documents, replacing any stored documents with the same ids. Documents
+    /// whose text is unchanged are not embedded again.
+    pub fn upsert_documents(&mut self, documents: &[Document]) -> Result<(), CollectionError> {
+        validate_documents(documents)?;
+
+        self.client.upsert_documents(self.uuid, documents)?;
+
+        Ok(())
+    }
+
+    /// Replaces the metadata of a stored document without touching its embedding.
+    pub fn update_metadata(
+        &mut self,
+        id: Uuid,
+        metadata: serde_json::Value,
+    ) -> Result<(), CollectionError> {
+        Ok(self.client.update_metadata(self.uuid, id, metadata)?)
+    }
+
+    /// Returns the metadata of the collection itself, without the reserved keys holding its
+    /// index configuration and embedding dimension and model.
+    pub fn metadata(&self) -> Result<serde_json::Value, CollectionError> {
+        Ok(self.client.get_collection_metadata(self.uuid)?)
+    }
+
+    /// Replaces the metadata of the collection itself. Reserved keys cannot be set and are kept.
+    pub fn set_metadata(&mut self, metadata: serde_json::Value) -> Result<(), CollectionError> {
+        Ok(self
+            .client
+            .update_collection_metadata(self.uuid, metadata)?)
+    }
+
+    /// Fetches the stored documents with the given ids. Unknown ids are skipped.
+    pub fn get(&self, ids: &[Uuid]) -> Result<Vec<Document>, CollectionError> {
+        Ok(self.client.get_documents(self.uuid, ids)?)
+    }
+
+    /// Returns the first `n` documents of the collection.
+    pub fn peek(&self, n: usize) -> Result<Vec<Document>, CollectionError> {
+        Ok(self
+            .client
+            .get_documents_page(self.uuid, &Filter::All, 0, n)?)
+    }
+
+    /// Returns up to `limit` documents matching `filter`, skipping the first `offset`.
+    pub fn get_where<F>(
+        &self,
+        filter: F,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<Document>, CollectionError>
+    where
+        F: TryInto<Filter>,
+        CollectionError: From<F::Error>,
+    {
+        let filter = filter.try_into()?;
+
+        Ok(self
+            .client
+            .get_documents_page(self.uuid, &filter, offset, limit)?)
+    }
+
+    /// Iterates over all documents matching `filter`, fetching `page_size` documents at a time.
+    pub fn documents<F>(
+        &self,
+        filter: F,
+        page_size: usize,
+    ) -> Result<Documents<'_>, CollectionError>
+    where
+        F: TryInto<Filter>,
+        CollectionError: From<F::Error>,
+    {
+        Ok(Documents {
+            collection: self,
+            filter: filter.try_into()?,
+            offset: 0,
+            page_size: page_size.max(1),
+            page: VecDeque::new(),
+            done: false,
+        })
+    }
+
+    /// Removes the documents with the given ids. Returns the number of removed documents.
+    pub fn delete(&mut self, ids: &[Uuid]) -> Result<usize, CollectionError> {
+        Ok(self.client.delete_documents(self.uuid, ids)?)
+    }
+
+    /// Removes all documents matching `filter`. Returns the number of removed documents.
+    pub fn delete_where<F>(&mut self, filter: F) -> Result<usize, CollectionError>
+    where
+        F: TryInto<Filter>,
+        CollectionError: From<F::Error>,
+    {
+        let filter = filter.try_into()?;
+
+        Ok(self.client.delete_documents_where(self.uuid, &filter)?)
+    }
+
+    /// Finds the `k` nearest documents for each query among those matching `filter`, which is
+    /// either a [`Filter`] or a `serde_json::Value` in the where-syntax.
+    pub fn query_documents<F>(
         &self,
         queries: &[&str],
-        _where: serde_json::Value,
+        filter: F,
         k: usize,
-    ) -> Result<Vec<Vec<QueryResult>>, CollectionError> {
-        Ok(self.client.query(self.uuid, queries, _where, k)?)
+    ) -> Result<Vec<Vec<QueryResult>>, CollectionError>
+    where
+        F: TryInto<Filter>,
+        CollectionError: From<F::Error>,
+    {
+        self.query_documents_with_options(queries, filter, k, &QueryOptions::default())
     }
+
+    /// Like [`Collection::query_documents`], with per-query search parameters.
+    pub fn query_documents_with_options<F>(
+        &self,
+        queries: &[&str],
+        filter: F,
+        k: usize,
+        options: &QueryOptions,
+    ) -> Result<Vec<Vec<QueryResult>>, CollectionError>
+    where
+        F: TryInto<Filter>,
+        CollectionError: From<F::Error>,
+    {
+        let filter = filter.try_into()?;
+
+        Ok(self.client.query(self.uuid, queries, &filter, k, options)?)
+    }
+
+    /// Finds the `k` nearest documents for each of the given embeddings, without running the
+    /// embedding function. The embeddings must have the dimension of the collection.
+    pub fn query_embeddings<F>(
+        &self,
+        embeddings: &[Embedding],
+        filter: F,
+        k: usize,
+    ) -> Result<Vec<Vec<QueryResult>>, CollectionError>
+    where
+        F: TryInto<Filter>,
+        CollectionError: From<F::Error>,
+    {
+        self.query_embeddings_with_options(embeddings, filter, k, &QueryOptions::default())
+    }
+
+    /// Like [`Collection::query_embeddings`], with per-query search parameters.
+    pub fn query_embeddings_with_options<F>(
+        &self,
+        embeddings: &[Embedding],
+        filter: F,
+        k: usize,
+        options: &QueryOptions,
+    ) -> Result<Vec<Vec<QueryResult>>, CollectionError>
+    where
+        F: TryInto<Filter>,
+        CollectionError: From<F::Error>,
+    {
+        validate_embeddings(embeddings)?;
+        let filter = filter.try_into()?;
+
+        Ok(self
+            .client
+            .query_embeddings(self.uuid, embeddings, &filter, k, options)?)
+    }
+}
+
+/// Iterator over the documents of a [`Collection`], see [`Collection::documents`].
+pub struct Documents<'a> {
+    collection: &'a Collection,
+    filter: Filter,
+    offset: usize,
+    page_size: usize,
+    page: VecDeque<Document>,
+    done: bool,
 }
 
-fn validate_documents(docs: &[Document]) -> Result<(), CollectionError> {
-    has_dups(docs.iter().map(|d| d.id))
+impl Documents<'_> {
+    /// Starts the iteration at the `offset`-th matching document.
+    pub fn skip_to(mut self, offset: usize) -> Self {
+        self.offset = offset;
+        self
+    }
+}
+
+impl Iterator for Documents<'_> {
+    type Item = Result<Document, CollectionError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.page.is_empty() && !self.done {
+            let page = self.collection.client.get_documents_page(
+                self.collection.uuid,
+                &self.filter,
+                self.offset,
+                self.page_size,
+            );
+
+            match page {
+                Ok(page) => {
+                    self.done = page.len() < self.page_size;
+                    self.offset += page.len();
+                    self.page.extend(page);
+                }
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e.into()));
+                }
+            }
+        }
+
+        self.page.pop_front().map(Ok)
+    }
+}
+
+fn validate_documents<'a>(
+    docs: impl IntoIterator<Item = &'a Document>,
+) -> Result<(), CollectionError> {
+    has_dups(docs.into_iter().map(|d| d.id))
         .then_some(())
         .ok_or(CollectionError::DuplicateError)
 }
 
+fn validate_embeddings<'a>(
+    embeddings: impl IntoIterator<Item = &'a Embedding>,
+) -> Result<(), CollectionError> {
+    let mut dims = embeddings.into_iter().map(Embedding::dim);
+    let first = dims.next();
+
+    dims.all(|dim| Some(dim) == first)
+        .then_some(())
+        .ok_or(CollectionError::DimensionError)
+}
+
 fn has_dups<T>(iter: T) -> bool
 where
     T: IntoIterator,
@@ -77,6 +315,15 @@ pub enum CollectionError {
 
     #[error("Client operation failed: {0}")]
     ClientError(#[from] ClientError),
+
+    #[error("Invalid filter: {0}")]
+    FilterError(#[from] FilterError),
+}
+
+impl From<Infallible> for CollectionError {
+    fn from(value: Infallible) -> Self {
+        match value {}
+    }
 }
 
 #[cfg(test)]
@@ -85,12 +332,15 @@ mod test {
     use uuid::Uuid;
 
     use crate::{
-        client::{local::LocalClient, Client},
-        db::{duckdb::DuckDB, Db},
+        client::{local::LocalClient, Client, ClientError},
+        db::{duckdb::DuckDB, Db, DbError},
         embeddings::sentencetransformer::SentenceTransformerEmbeddings,
-        Document,
+        index::IndexConfig,
+        Document, Embedding,
     };
 
+    use super::CollectionError;
+
     #[test]
     pub fn test_collection() {
         let db = DuckDB::new(Default::default()).unwrap();
@@ -113,6 +363,262 @@ mod test {
         let res = collection
             .query_documents(&["hello"], serde_json::json!({ "source": "notion" }), 1)
             .unwrap();
-        assert_eq!(res[0][0].text, docs[0].text);
+        assert_eq!(res[0][0].text.as_ref(), Some(&docs[0].text));
+    }
+
+    #[test]
+    pub fn test_collection_metadata() {
+        let db = DuckDB::new(Default::default()).unwrap();
+        db.init().unwrap();
+
+        let embedding_fn = SentenceTransformerEmbeddings::new();
+
+        let mut client = LocalClient::init(db, embedding_fn).unwrap();
+
+        let mut collection = client
+            .create_collection_with_metadata(
+                "collection1",
+                IndexConfig::default(),
+                json!({ "owner": "search", "source": "notion" }),
+            )
+            .unwrap();
+        assert_eq!(
+            collection.metadata().unwrap(),
+            json!({ "owner": "search", "source": "notion" })
+        );
+
+        let docs = vec![Document::new(
+            "hello world!".to_string(),
+            json!({"source": "notion"}),
+        )];
+        collection.add_documents(&docs).unwrap();
+
+        collection.set_metadata(json!({ "owner": "ml" })).unwrap();
+        let mut collection = client.get_collection("collection1").unwrap().unwrap();
+        assert_eq!(collection.metadata().unwrap(), json!({ "owner": "ml" }));
+
+        // the index configuration and recorded embeddings survive the update
+        let res = collection
+            .query_documents(&["hello"], json!({}), 1)
+            .unwrap();
+        assert_eq!(res[0][0].uuid, docs[0].id);
+
+        assert!(matches!(
+            collection.set_metadata(json!({ "hnsw:M": 4 })),
+            Err(CollectionError::ClientError(ClientError::DbError(
+                DbError::InvalidValueError(_)
+            )))
+        ));
+        assert!(collection.set_metadata(json!(["owner"])).is_err());
+        assert!(client
+            .create_collection_with_metadata(
+                "collection2",
+                IndexConfig::default(),
+                json!({ "embedding:model": "other" }),
+            )
+            .is_err());
+    }
+
+    #[test]
+    pub fn test_collection_query_embeddings() {
+        let db = DuckDB::new(Default::default()).unwrap();
+        db.init().unwrap();
+
+        let embedding_fn = SentenceTransformerEmbeddings::new();
+
+        let mut client = LocalClient::init(db, embedding_fn).unwrap();
+
+        let mut collection = client.create_collection("collection1").unwrap();
+
+        let docs = vec![
+            Document::new("hello world!".to_string(), json!({"source": "notion"})),
+            Document::new("lychees are sweet".to_string(), json!({"source": "slack"})),
+        ];
+        collection.add_documents(&docs).unwrap();
+
+        let embeddings = client.embed(&["hello"]).unwrap();
+        let res = collection
+            .query_embeddings(&embeddings, json!({}), 1)
+            .unwrap();
+        assert_eq!(res[0][0].uuid, docs[0].id);
+
+        let dim = embeddings[0].dim();
+        assert!(matches!(
+            collection.query_embeddings(
+                &[vec![0.0; dim].into(), vec![0.0; 3].into()],
+                json!({}),
+                1
+            ),
+            Err(CollectionError::DimensionError)
+        ));
+        assert!(matches!(
+            collection.query_embeddings(&[vec![0.0; dim + 1].into()], json!({}), 1),
+            Err(CollectionError::ClientError(ClientError::DbError(
+                DbError::DimensionError { .. }
+            )))
+        ));
+    }
+
+    #[test]
+    pub fn test_collection_add_with_embeddings() {
+        let db = DuckDB::new(Default::default()).unwrap();
+        db.init().unwrap();
+
+        let embedding_fn = SentenceTransformerEmbeddings::new();
+
+        let mut client = LocalClient::init(db, embedding_fn).unwrap();
+
+        let mut collection = client.create_collection("collection1").unwrap();
+
+        let docs: Vec<_> = (0..3)
+            .map(|i| {
+                let mut embedding = vec![0.0; 4];
+                embedding[i] = 1.0;
+                (
+                    Document::new(format!("document {i}"), json!({ "i": i })),
+                    Embedding::from(embedding),
+                )
+            })
+            .collect();
+        collection.add_documents_with_embeddings(&docs).unwrap();
+
+        let res = collection
+            .query_embeddings(&[vec![0.0, 1.0, 0.0, 0.0].into()], json!({}), 1)
+            .unwrap();
+        assert_eq!(res[0][0].uuid, docs[1].0.id);
+        assert_eq!(res[0][0].text, Some(docs[1].0.text.clone()));
+
+        let mixed: [(Document, Embedding); 2] = [
+            (
+                Document::new("a".to_string(), json!({})),
+                vec![0.0; 4].into(),
+            ),
+            (
+                Document::new("b".to_string(), json!({})),
+                vec![0.0; 5].into(),
+            ),
+        ];
+        assert!(matches!(
+            collection.add_documents_with_embeddings(&mixed),
+            Err(CollectionError::DimensionError)
+        ));
+
+        let wrong: [(Document, Embedding); 1] = [(
+            Document::new("c".to_string(), json!({})),
+            vec![0.0; 5].into(),
+        )];
+        assert!(matches!(
+            collection.add_documents_with_embeddings(&wrong),
+            Err(CollectionError::ClientError(ClientError::DbError(
+                DbError::DimensionError {
+                    expected: 4,
+                    found: 5
+                }
+            )))
+        ));
+        assert_eq!(collection.peek(10).unwrap().len(), 3);
+    }
+
+    #[test]
+    pub fn test_collection_upsert() {
+        let db = DuckDB::new(Default::default()).unwrap();
+        db.init().unwrap();
+
+        let embedding_fn = SentenceTransformerEmbeddings::new();
+
+        let mut client = LocalClient::init(db, embedding_fn).unwrap();
+
+        let mut collection = client.create_collection("collection1").unwrap();
+
+        let mut doc = Document::new("hello world!".to_string(), json!({"source": "notion"}));
+        collection
+            .add_documents(std::slice::from_ref(&doc))
+            .unwrap();
+
+        doc.set_metadata(json!({"source": "slack"}));
+        collection
+            .upsert_documents(std::slice::from_ref(&doc))
+            .unwrap();
+
+        doc.text = "goodbye world!".to_string();
+        collection
+            .upsert_documents(std::slice::from_ref(&doc))
+            .unwrap();
+
+        let res = collection
+            .query_documents(&["goodbye"], json!({"source": "slack"}), 2)
+            .unwrap();
+        assert_eq!(res[0].len(), 1);
+        assert_eq!(res[0][0].text.as_ref(), Some(&doc.text));
+
+        collection
+            .update_metadata(doc.id, json!({"source": "notion"}))
+            .unwrap();
+
+        let res = collection
+            .query_documents(&["goodbye"], json!({"source": "notion"}), 2)
+            .unwrap();
+        assert_eq!(res[0].len(), 1);
+    }
+
+    #[test]
+    pub fn test_collection_get() {
+        let db = DuckDB::new(Default::default()).unwrap();
+        db.init().unwrap();
+
+        let embedding_fn = SentenceTransformerEmbeddings::new();
+
+        let mut client = LocalClient::init(db, embedding_fn).unwrap();
+
+        let mut collection = client.create_collection("collection1").unwrap();
+
+        let docs: Vec<_> = (0..5)
+            .map(|i| Document::new(format!("document {i}"), json!({ "i": i })))
+            .collect();
+        collection.add_documents(&docs).unwrap();
+
+        let fetched = collection.get(&[docs[1].id, Uuid::new_v4()]).unwrap();
+        assert_eq!(fetched.len(), 1);
+        assert_eq!(fetched[0].text, docs[1].text);
+
+        assert_eq!(collection.peek(2).unwrap().len(), 2);
+
+        let ids: Vec<_> = collection
+            .documents(json!({ "i": { "$gte": 1 } }), 2)
+            .unwrap()
+            .map(|doc| doc.unwrap().id)
+            .collect();
+        assert_eq!(ids, docs[1..].iter().map(|d| d.id).collect::<Vec<_>>());
+    }
+
+    #[test]
+    pub fn test_collection_delete() {
+        let db = DuckDB::new(Default::default()).unwrap();
+        db.init().unwrap();
+
+        let embedding_fn = SentenceTransformerEmbeddings::new();
+
+        let mut client = LocalClient::init(db, embedding_fn).unwrap();
+
+        let mut collection = client.create_collection("collection1").unwrap();
+
+        let docs = vec![
+            Document::new("hello world!".to_string(), json!({"source": "notion"})),
+            Document::new("hello there!".to_string(), json!({"source": "slack"})),
+            Document::new("hello again!".to_string(), json!({"source": "slack"})),
+        ];
+
+        collection.add_documents(&docs).unwrap();
+
+        assert_eq!(1, collection.delete(&[docs[0].id]).unwrap());
+        assert_eq!(
+            2,
+            collection.delete_where(json!({"source": "slack"})).unwrap()
+        );
+
+        let res = collection
+            .query_documents(&["hello"], json!({}), 3)
+            .unwrap();
+        assert!(res[0].is_empty());
     }
 }
@@ -1,27 +1,70 @@
-use std::{cell::RefCell, collections::HashMap};
+use std::{
+    cmp::min,
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, RwLock},
+};
 
-use duckdb::{params, Config};
+use duckdb::{
+    arrow::{
+        array::{Array, ArrayRef, Float32Array, Int64Array, ListArray, StringArray},
+        datatypes::Float32Type,
+        record_batch::RecordBatch,
+    },
+    params, params_from_iter,
+    types::Value as SqlValue,
+    Config, Connection,
+};
 
 use uuid::Uuid;
 
 use crate::{
-    index::{Index, IndexEntry},
-    Embedding, QueryResult,
+    filter::{Filter, FilterValue},
+    index::{self, IndexConfig, IndexEntry, IndexError, VectorIndex},
+    Embedding, Include, QueryOptions, QueryResult,
 };
 
 use super::{
-    model::{CollectionModel, EmbeddingModel},
+    model::{
+        is_reserved_key, validate_user_metadata, CollectionModel, EmbeddingModel, DIMENSION_KEY,
+        MODEL_KEY,
+    },
+    pool::{ConnectionPool, PooledConnection},
     Db, DbError,
 };
 
-use serde_json::{json, Value};
+use serde_json::{json, Map, Value};
 
 pub type DuckDBConfig = duckdb::Config;
 
+// Rows read at a time when an index is rebuilt from the `embeddings` table.
+const REBUILD_BATCH_SIZE: usize = 10_000;
+
+// Indexes are rebuilt once removed entries take up more than 1 / `MAX_TOMBSTONE_SHARE` of them.
+const MAX_TOMBSTONE_SHARE: usize = 4;
+
+// Filters matching at most this many rows are answered with an exact scan instead of the index.
+const EXACT_SEARCH_THRESHOLD: usize = 1024;
+
+// Index of one collection. Queries share it while writes to the collection hold it
+// exclusively, so they see the table and the index change together.
+type SharedIndex = Arc<RwLock<Box<dyn VectorIndex>>>;
+
+// Slot of a collection's index, empty until the index is first used. Its mutex is held while
+// the index is loaded, so the index is loaded once without blocking other collections.
+type IndexSlot = Arc<Mutex<Option<SharedIndex>>>;
+
+// Held while the metadata of a collection is read, changed and written back.
+type MetadataLock = Arc<Mutex<()>>;
+
+/// DuckDB backed store. It can be shared between threads: each operation runs on its own
+/// pooled connection, and the index of each collection is behind its own read/write lock.
 #[derive(Debug)]
 pub struct DuckDB {
-    conn: duckdb::Connection,
-    index: RefCell<HashMap<Uuid, Index>>,
+    pool: ConnectionPool,
+    index: RwLock<HashMap<Uuid, IndexSlot>>,
+    metadata_locks: Mutex<HashMap<Uuid, MetadataLock>>,
+    path: Option<PathBuf>,
 }
 
 impl DuckDB {
@@ -30,12 +73,48 @@ impl DuckDB {
             .map_err(|e| DbError::DbInitError(e.into()))?;
         let index = HashMap::new().into();
 
-        Ok(DuckDB { conn, index })
+        Ok(DuckDB {
+            pool: ConnectionPool::new(conn),
+            index,
+            metadata_locks: Default::default(),
+            path: None,
+        })
+    }
+
+    /// Opens (or creates) a database backed by the file at `path`.
+    ///
+    /// Data written through this instance is flushed to the file by [`Db::persist`].
+    pub fn open<P: AsRef<Path>>(path: P, config: Config) -> Result<Self, DbError> {
+        let path = path.as_ref().to_path_buf();
+        let conn = duckdb::Connection::open_with_flags(&path, config)
+            .map_err(|e| DbError::DbInitError(e.into()))?;
+        let index = HashMap::new().into();
+
+        Ok(DuckDB {
+            pool: ConnectionPool::new(conn),
+            index,
+            metadata_locks: Default::default(),
+            path: Some(path),
+        })
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    fn conn(&self) -> Result<PooledConnection<'_>, DbError> {
+        Ok(self.pool.get()?)
     }
 
     fn init_collections_table(&self) -> Result<(), DbError> {
-        self.conn.execute(
-            "CREATE TABLE collections (uuid STRING, name STRING, metadata JSON)",
+        self.conn()?.execute(
+            "CREATE TABLE IF NOT EXISTS collections (uuid STRING, name STRING, metadata JSON, generation BIGINT DEFAULT 0)",
+            [],
+        )?;
+
+        // stores created before the generation was tracked
+        self.conn()?.execute(
+            "ALTER TABLE collections ADD COLUMN IF NOT EXISTS generation BIGINT DEFAULT 0",
             [],
         )?;
 
@@ -43,72 +122,516 @@ impl DuckDB {
     }
 
     fn init_embeddings_table(&self) -> Result<(), DbError> {
-        self.conn
+        self.conn()?
         .execute(
-            "CREATE TABLE embeddings (collection_uuid STRING, uuid STRING, embedding JSON, text STRING, metadata JSON)",
+            "CREATE TABLE IF NOT EXISTS embeddings (collection_uuid STRING, uuid STRING, embedding FLOAT[], text STRING, metadata JSON)",
             []
         )?;
 
+        self.migrate_embeddings_column()?;
+
         Ok(())
     }
 
-    fn create_where_clause(&self, _where: Value, collection_uuid: Uuid) -> Result<String, DbError> {
-        let mut clauses = vec![format!("collection_uuid = '{}'", collection_uuid.urn().to_string())];
-
-        self.format_where(
-            _where.as_object().ok_or_else(|| {
-                DbError::InvalidValueError(format!("invalid where clause: {_where}"))
-            })?,
-            &mut clauses,
+    /// Stores created before embeddings were kept as `FLOAT[]` have a `JSON` embedding column.
+    /// Converts such a column in place.
+    fn migrate_embeddings_column(&self) -> Result<(), DbError> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare(
+            "SELECT data_type FROM information_schema.columns WHERE table_name = 'embeddings' AND column_name = 'embedding'",
         )?;
-        
-        let where_str = clauses.join(" AND ");
+        let data_type: String = stmt.query_row([], |row| row.get(0))?;
+
+        if data_type.eq_ignore_ascii_case("JSON") {
+            conn.execute_batch(
+                "ALTER TABLE embeddings ALTER embedding TYPE FLOAT[] USING CAST(CAST(embedding AS VARCHAR) AS FLOAT[]);",
+            )?;
+        }
+
+        Ok(())
+    }
+
+    fn select_embeddings<P: duckdb::Params>(
+        &self,
+        sql: &str,
+        params: P,
+    ) -> Result<Vec<EmbeddingModel>, DbError> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare(sql)?;
+
+        let mut embeddings = Vec::new();
+        for batch in stmt.query_arrow(params)? {
+            embeddings.extend(embeddings_from_batch(&batch));
+        }
+
+        Ok(embeddings)
+    }
+
+    fn create_where_clause(
+        &self,
+        filter: &Filter,
+        collection_uuid: Uuid,
+        params: &mut Vec<SqlValue>,
+    ) -> String {
+        params.push(SqlValue::Text(collection_uuid.urn().to_string()));
+        let filter_sql = compile_filter(filter, params);
 
-        Ok(format!("WHERE {where_str}"))
+        format!("WHERE collection_uuid = ? AND {filter_sql}")
     }
 
     fn get_nearest_neighbors(
         &self,
         collection_uuid: Uuid,
         embeddings: &[Embedding],
-        _where: Value,
+        filter: &Filter,
+        k: usize,
+        options: &QueryOptions,
+    ) -> Result<Vec<Vec<(Uuid, f32)>>, DbError> {
+        let shared = self.collection_index(collection_uuid)?;
+        let idx = shared.read().expect("index lock poisoned");
+
+        let mut params = vec![];
+        let where_clause = self.create_where_clause(filter, collection_uuid, &mut params);
+
+        // Unfiltered queries never need to look at the table. Filters are evaluated once, the
+        // index then only returns the uuids that passed.
+        let passed = match filter {
+            Filter::All => None,
+            _ => Some(select_uuids(&self.conn()?, &where_clause, params.clone())?),
+        };
+        let matching = passed.as_ref().map_or(idx.len(), HashSet::len);
+
+        if idx.is_exact() && passed.is_none() {
+            return Ok(idx.get_nearest_neighbors(embeddings, k, options, &|_| true));
+        }
+
+        // Few rows passing a filter are cheaper and exact to scan directly. Unfiltered queries
+        // always use the index and its search options.
+        if passed.is_some() && matching <= EXACT_SEARCH_THRESHOLD {
+            let vectors = self.select_vectors(&where_clause, params)?;
+
+            return Ok(embeddings
+                .iter()
+                .map(|embedding| exact_nearest_neighbors(idx.as_ref(), &vectors, embedding, k))
+                .collect());
+        }
+
+        let n = idx
+            .rescore_factor()
+            .map_or(k, |factor| k.saturating_mul(factor));
+        let candidates = match &passed {
+            Some(passed) => {
+                idx.get_nearest_neighbors(embeddings, n, options, &|uuid| passed.contains(uuid))
+            }
+            None => idx.get_nearest_neighbors(embeddings, n, options, &|_| true),
+        };
+
+        let mut neighs = match idx.rescore_factor() {
+            Some(_) => self.rescore(collection_uuid, idx.as_ref(), embeddings, candidates, k)?,
+            None => candidates,
+        };
+
+        // Approximate indexes can miss matching points for selective filters. Those queries
+        // fall back to scanning the matching vectors.
+        let expected = min(k, matching);
+        if neighs.iter().any(|row| row.len() < expected) {
+            let vectors = self.select_vectors(&where_clause, params)?;
+
+            for (row, embedding) in neighs.iter_mut().zip(embeddings) {
+                if row.len() < expected {
+                    *row = exact_nearest_neighbors(idx.as_ref(), &vectors, embedding, k);
+                }
+            }
+        }
+
+        Ok(neighs)
+    }
+
+    // Recomputes the distances of the candidates of quantized indexes from the full precision
+    // embeddings and keeps the `k` nearest of each row.
+    fn rescore(
+        &self,
+        collection_uuid: Uuid,
+        idx: &dyn VectorIndex,
+        embeddings: &[Embedding],
+        candidates: Vec<Vec<(Uuid, f32)>>,
         k: usize,
     ) -> Result<Vec<Vec<(Uuid, f32)>>, DbError> {
-        let index = self.index.borrow();
-        let idx = index
+        let uuids: Vec<Uuid> = candidates
+            .iter()
+            .flatten()
+            .map(|(uuid, _)| *uuid)
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+        if uuids.is_empty() {
+            return Ok(candidates);
+        }
+
+        let (where_clause, params) = uuids_where_clause(collection_uuid, &uuids);
+        let vectors: HashMap<Uuid, Vec<f32>> = self
+            .select_vectors(&where_clause, params)?
+            .into_iter()
+            .collect();
+
+        Ok(candidates
+            .into_iter()
+            .zip(embeddings)
+            .map(|(row, embedding)| {
+                let mut row: Vec<_> = row
+                    .into_iter()
+                    .filter_map(|(uuid, _)| {
+                        vectors
+                            .get(&uuid)
+                            .map(|v| (uuid, idx.distance(embedding.get(), v)))
+                    })
+                    .collect();
+                row.sort_by(|a, b| a.1.total_cmp(&b.1));
+                row.truncate(k);
+                row
+            })
+            .collect())
+    }
+
+    fn delete_matching(
+        &self,
+        collection_uuid: Uuid,
+        idx: &mut Box<dyn VectorIndex>,
+        where_clause: &str,
+        params: Vec<SqlValue>,
+    ) -> Result<usize, DbError> {
+        let mut conn = self.conn()?;
+        let tx = conn.transaction()?;
+        let uuids = delete_rows(&tx, where_clause, params)?;
+        if !uuids.is_empty() {
+            bump_generation(&tx, collection_uuid)?;
+        }
+        tx.commit()?;
+
+        idx.remove(&uuids);
+        self.compact_index(collection_uuid, idx)?;
+
+        Ok(uuids.len())
+    }
+
+    fn insert_embeddings(
+        &self,
+        collection_uuid: Uuid,
+        idx: &mut dyn VectorIndex,
+        embeddings: Vec<EmbeddingModel>,
+    ) -> Result<(), DbError> {
+        let mut conn = self.conn()?;
+        let tx = conn.transaction()?;
+        append_embeddings(&tx, collection_uuid, &embeddings)?;
+        bump_generation(&tx, collection_uuid)?;
+        tx.commit()?;
+
+        // the index only changes once all rows are committed
+        add_to_index(idx, embeddings)
+    }
+
+    // Dimension of the embeddings stored in the collection, `None` while it is empty.
+    // Collections filled before the dimension was recorded fall back to a stored embedding.
+    fn collection_dimension(&self, collection_uuid: Uuid) -> Result<Option<usize>, DbError> {
+        let collection = self
+            .get_collection_by_uuid(collection_uuid)?
+            .ok_or(DbError::CollectionNotFoundError(collection_uuid))?;
+        if let Some(dimension) = collection.dimension() {
+            return Ok(Some(dimension));
+        }
+
+        let conn = self.conn()?;
+        let mut stmt = conn
+            .prepare("SELECT len(embedding) FROM embeddings WHERE collection_uuid = ? LIMIT 1")?;
+        let mut dims = stmt.query_map([collection_uuid.urn().to_string()], |row| row.get(0))?;
+
+        Ok(dims.next().transpose()?)
+    }
+
+    // Checks that embeddings of dimensions `dims` can be stored in or searched for in the
+    // collection. An empty collection accepts any dimension, as long as all are the same.
+    fn validate_dimension(
+        &self,
+        collection_uuid: Uuid,
+        dims: impl IntoIterator<Item = usize>,
+    ) -> Result<(), DbError> {
+        let mut dims = dims.into_iter().peekable();
+        let Some(&first) = dims.peek() else {
+            return Ok(());
+        };
+
+        let expected = self.collection_dimension(collection_uuid)?.unwrap_or(first);
+
+        match dims.find(|&dim| dim != expected) {
+            Some(found) => Err(DbError::DimensionError { expected, found }),
+            None => Ok(()),
+        }
+    }
+
+    // Stores `dimension` in the metadata of the collection if it is not recorded yet.
+    fn record_dimension(&self, collection_uuid: Uuid, dimension: usize) -> Result<(), DbError> {
+        let lock = self.metadata_lock(collection_uuid)?;
+        let _guard = lock.lock().expect("metadata lock poisoned");
+
+        let collection = self
+            .get_collection_by_uuid(collection_uuid)?
+            .ok_or(DbError::CollectionNotFoundError(collection_uuid))?;
+        if collection.dimension().is_some() {
+            return Ok(());
+        }
+
+        let mut metadata = collection.metadata;
+        metadata[DIMENSION_KEY] = dimension.into();
+
+        self.write_collection_metadata(collection_uuid, &metadata)
+    }
+
+    // Returns the lock serializing the metadata writes of a collection. Like index slots, locks
+    // are only added for existing collections.
+    fn metadata_lock(&self, uuid: Uuid) -> Result<MetadataLock, DbError> {
+        if let Some(lock) = self
+            .metadata_locks
+            .lock()
+            .expect("metadata lock poisoned")
+            .get(&uuid)
+        {
+            return Ok(lock.clone());
+        }
+
+        if self.get_collection_by_uuid(uuid)?.is_none() {
+            return Err(DbError::CollectionNotFoundError(uuid));
+        }
+
+        Ok(self
+            .metadata_locks
+            .lock()
+            .expect("metadata lock poisoned")
+            .entry(uuid)
+            .or_default()
+            .clone())
+    }
+
+    // Callers hold the collection's metadata lock, see `metadata_lock`.
+    fn write_collection_metadata(&self, uuid: Uuid, metadata: &Value) -> Result<(), DbError> {
+        self.conn()?.execute(
+            "UPDATE collections SET metadata = ? WHERE uuid = ?",
+            params![metadata, uuid.urn().to_string()],
+        )?;
+
+        Ok(())
+    }
+
+    fn select_vectors(
+        &self,
+        where_clause: &str,
+        params: Vec<SqlValue>,
+    ) -> Result<Vec<(Uuid, Vec<f32>)>, DbError> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT uuid, embedding FROM embeddings {where_clause}"
+        ))?;
+
+        let mut vectors = Vec::new();
+        for batch in stmt.query_arrow(params_from_iter(params))? {
+            vectors.extend(vectors_from_batch(&batch));
+        }
+
+        Ok(vectors)
+    }
+
+    /// Returns the in-memory index for `collection_uuid`, building it from the rows in the
+    /// `embeddings` table if the collection was not created by this instance.
+    fn collection_index(&self, collection_uuid: Uuid) -> Result<SharedIndex, DbError> {
+        let slot = self.index_slot(collection_uuid)?;
+
+        // held while loading so that concurrent first queries load the index only once
+        let mut loaded = slot.lock().expect("index lock poisoned");
+        if let Some(idx) = loaded.as_ref() {
+            return Ok(idx.clone());
+        }
+
+        let idx = Arc::new(RwLock::new(self.load_collection_index(collection_uuid)?));
+        *loaded = Some(idx.clone());
+
+        Ok(idx)
+    }
+
+    /// Returns the index of `collection_uuid` if it is loaded already, waiting for a running
+    /// load to finish.
+    fn loaded_index(&self, collection_uuid: Uuid) -> Option<SharedIndex> {
+        let slot = self
+            .index
+            .read()
+            .expect("index lock poisoned")
+            .get(&collection_uuid)
+            .cloned()?;
+
+        let loaded = slot.lock().expect("index lock poisoned");
+        loaded.clone()
+    }
+
+    // Returns the slot of a collection, adding an empty one on first access. The map is only
+    // locked briefly, indexes are loaded under the lock of their own slot.
+    fn index_slot(&self, collection_uuid: Uuid) -> Result<IndexSlot, DbError> {
+        if let Some(slot) = self
+            .index
+            .read()
+            .expect("index lock poisoned")
             .get(&collection_uuid)
-            .expect("index does not exist for collection");
+        {
+            return Ok(slot.clone());
+        }
 
-        let where_clause = self.create_where_clause(_where, collection_uuid)?;
-        
-        let sql = &format!("SELECT uuid FROM embeddings {where_clause}");
+        // unknown uuids must not leave slots behind
+        if self.get_collection_by_uuid(collection_uuid)?.is_none() {
+            return Err(DbError::CollectionNotFoundError(collection_uuid));
+        }
 
-        let mut stmt = self
-            .conn
-            .prepare(sql)?;
+        Ok(self
+            .index
+            .write()
+            .expect("index lock poisoned")
+            .entry(collection_uuid)
+            .or_default()
+            .clone())
+    }
 
-        let mapped_rows = stmt.query_map([], |row| {
-            row.get::<_, String>(0)
-        })?;
+    // Loads the dumped index of a collection, or rebuilds it from the `embeddings` table.
+    fn load_collection_index(
+        &self,
+        collection_uuid: Uuid,
+    ) -> Result<Box<dyn VectorIndex>, DbError> {
+        let collection = self
+            .get_collection_by_uuid(collection_uuid)?
+            .ok_or(DbError::CollectionNotFoundError(collection_uuid))?;
+        let config = IndexConfig::from_metadata(&collection.metadata)?;
+
+        if let Some(idx) = self.load_index_dump(collection_uuid, &config)? {
+            return Ok(idx);
+        }
+
+        self.build_index(collection_uuid, &config)
+    }
+
+    // Rebuilds the index of a collection once too many of its entries are removed ones. HNSW
+    // graphs only hide removed points, which keep costing memory and search time. Callers
+    // hold the index write lock.
+    fn compact_index(
+        &self,
+        collection_uuid: Uuid,
+        idx: &mut Box<dyn VectorIndex>,
+    ) -> Result<(), DbError> {
+        let tombstones = idx.tombstones();
+        if tombstones * MAX_TOMBSTONE_SHARE <= idx.len() + tombstones {
+            return Ok(());
+        }
+
+        let collection = self
+            .get_collection_by_uuid(collection_uuid)?
+            .ok_or(DbError::CollectionNotFoundError(collection_uuid))?;
+        let config = IndexConfig::from_metadata(&collection.metadata)?;
+        *idx = self.build_index(collection_uuid, &config)?;
 
-        let mut uuids = Vec::new();
-        for row in mapped_rows {
-            uuids.push(row?.parse().expect("failed to parse uuid from string"))
+        Ok(())
+    }
+
+    // Builds an index of the vectors stored in the collection. Only uuids and vectors are read,
+    // `REBUILD_BATCH_SIZE` rows at a time.
+    fn build_index(
+        &self,
+        collection_uuid: Uuid,
+        config: &IndexConfig,
+    ) -> Result<Box<dyn VectorIndex>, DbError> {
+        let mut idx = index::new_index(config, self.index_basename(collection_uuid).as_deref())?;
+
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare(
+            "SELECT uuid, embedding, rowid FROM embeddings WHERE collection_uuid = ? AND rowid > ? ORDER BY rowid LIMIT ?",
+        )?;
+
+        let mut last_rowid = -1;
+        loop {
+            let mut rows = 0;
+            for batch in stmt.query_arrow(params![
+                collection_uuid.urn().to_string(),
+                last_rowid,
+                REBUILD_BATCH_SIZE as i64
+            ])? {
+                if batch.num_rows() == 0 {
+                    continue;
+                }
+
+                rows += batch.num_rows();
+                last_rowid = batch
+                    .column(2)
+                    .as_any()
+                    .downcast_ref::<Int64Array>()
+                    .expect("expected an integer column")
+                    .value(batch.num_rows() - 1);
+
+                for (uuid, e) in vectors_from_batch(&batch) {
+                    idx.add(IndexEntry { e: e.into(), uuid })?;
+                }
+            }
+
+            if rows < REBUILD_BATCH_SIZE {
+                return Ok(idx);
+            }
         }
+    }
+
+    fn index_dir(&self) -> Option<PathBuf> {
+        self.path.as_ref().map(|path| {
+            let mut dir = path.as_os_str().to_owned();
+            dir.push(".index");
+            PathBuf::from(dir)
+        })
+    }
+
+    // Prefix of the files of a collection's index.
+    fn index_basename(&self, collection_uuid: Uuid) -> Option<PathBuf> {
+        self.index_dir()
+            .map(|dir| dir.join(collection_uuid.simple().to_string()))
+    }
+
+    /// Loads the dumped index of a collection, if there is one and it was dumped at the
+    /// collection's current generation. Stale dumps are ignored so that the caller rebuilds
+    /// them.
+    fn load_index_dump(
+        &self,
+        collection_uuid: Uuid,
+        config: &IndexConfig,
+    ) -> Result<Option<Box<dyn VectorIndex>>, DbError> {
+        let Some(basename) = self.index_basename(collection_uuid) else {
+            return Ok(None);
+        };
 
-        let uuids = idx.get_nearest_neighbors(embeddings, k, &uuids);
+        // dumps written before the generation was tracked have no generation file
+        let dumped = std::fs::read_to_string(generation_path(&basename))
+            .ok()
+            .and_then(|generation| generation.trim().parse::<i64>().ok());
+        if dumped != Some(collection_generation(&self.conn()?, collection_uuid)?) {
+            return Ok(None);
+        }
 
-        Ok(uuids)
+        match index::load_index(&basename, config) {
+            Ok(idx) => Ok(idx),
+            Err(_) => Ok(None),
+        }
     }
 
-    fn get_embedding_from_uuid(&self, uuid: Uuid) -> Result<EmbeddingModel, DbError> {
-        let mut stmt = self
-            .conn
-            .prepare("SELECT * FROM embeddings WHERE uuid = ?")?;
+    fn remove_index_dump(&self, collection_uuid: Uuid) -> Result<(), DbError> {
+        if let Some(basename) = self.index_basename(collection_uuid) {
+            index::remove_dump(&basename)?;
+            match std::fs::remove_file(generation_path(&basename)) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                    return Err(IndexError::from(e).into())
+                }
+                _ => {}
+            }
+        }
 
-        Ok(stmt.query_row([uuid.urn().to_string()], |row| {
-            EmbeddingModel::try_from(row)
-        })?)
+        Ok(())
     }
 }
 
@@ -117,15 +640,14 @@ impl Db for DuckDB {
         self.init_collections_table()?;
         self.init_embeddings_table()?;
 
-        self.conn.execute("LOAD 'json';", [])?;
+        self.conn()?.execute("LOAD 'json';", [])?;
 
         Ok(())
     }
 
     fn get_collection(&self, name: &str) -> Result<Option<CollectionModel>, DbError> {
-        let mut sql = self
-            .conn
-            .prepare("SELECT * FROM collections WHERE name = ?")?;
+        let conn = self.conn()?;
+        let mut sql = conn.prepare("SELECT * FROM collections WHERE name = ?")?;
         let mut collections = sql.query_map([name], |row| CollectionModel::try_from(row))?;
 
         match collections.next() {
@@ -137,34 +659,66 @@ impl Db for DuckDB {
         }
     }
 
-    fn create_collection(&self, name: &str) -> Result<CollectionModel, DbError> {
+    fn get_collection_by_uuid(&self, uuid: Uuid) -> Result<Option<CollectionModel>, DbError> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare("SELECT * FROM collections WHERE uuid = ?")?;
+        let mut collections = stmt.query_map([uuid.urn().to_string()], |row| {
+            CollectionModel::try_from(row)
+        })?;
+
+        Ok(collections.next().transpose()?)
+    }
+
+    fn create_collection(&self, name: &str, metadata: Value) -> Result<CollectionModel, DbError> {
+        let mut metadata = match metadata {
+            Value::Object(map) => map,
+            Value::Null => Default::default(),
+            _ => {
+                return Err(DbError::InvalidValueError(format!(
+                    "collection metadata must be an object, found {metadata}"
+                )))
+            }
+        };
+
+        // the resolved configuration is always stored so that defaults can change safely
+        let config = IndexConfig::from_metadata(&Value::Object(metadata.clone()))?;
+        config.write_metadata(&mut metadata);
+
         let collection = CollectionModel {
             uuid: Uuid::new_v4(),
             name: name.to_string(),
-            metadata: json!({}),
+            metadata: Value::Object(metadata),
         };
 
-        self.conn.execute(
+        let idx = index::new_index(&config, self.index_basename(collection.uuid).as_deref())?;
+
+        self.conn()?.execute(
             "INSERT INTO collections (uuid, name, metadata) VALUES (?, ?, ?)",
             params![collection.uuid.urn().to_string(), name, collection.metadata],
         )?;
 
-        self.index
-            .borrow_mut()
-            .insert(collection.uuid, Index::new());
+        self.index.write().expect("index lock poisoned").insert(
+            collection.uuid,
+            Arc::new(Mutex::new(Some(Arc::new(RwLock::new(idx))))),
+        );
 
         Ok(collection)
     }
 
-    fn get_or_create_collection(&self, name: &str) -> Result<CollectionModel, DbError> {
+    fn get_or_create_collection(
+        &self,
+        name: &str,
+        metadata: Value,
+    ) -> Result<CollectionModel, DbError> {
         match self.get_collection(name)? {
             Some(collection) => Ok(collection),
-            None => self.create_collection(name),
+            None => self.create_collection(name, metadata),
         }
     }
 
     fn list_collections(&self) -> Result<Vec<CollectionModel>, DbError> {
-        let mut stmt = self.conn.prepare("SELECT * FROM collections")?;
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare("SELECT * FROM collections")?;
         let rows = stmt.query_map([], |row| CollectionModel::try_from(row))?;
 
         let mut collections = vec![];
@@ -191,13 +745,13 @@ impl Db for DuckDB {
                     )));
                 }
 
-                self.conn.execute(
+                self.conn()?.execute(
                     "UPDATE collections SET name = ? WHERE uuid = ?",
                     params![new_name, uuid.urn().to_string()],
                 )?;
             }
             None => {
-                self.conn.execute(
+                self.conn()?.execute(
                     "UPDATE collections SET name = ? WHERE uuid = ?",
                     params![new_name, uuid.urn().to_string()],
                 )?;
@@ -212,268 +766,377 @@ impl Db for DuckDB {
         }
     }
 
+    fn delete_collection(&self, uuid: Uuid) -> Result<(), DbError> {
+        // the slot lock keeps the index from being loaded meanwhile, the index lock waits for
+        // running writes. Writers queued behind it fail once the collection is gone.
+        let slot = self.index_slot(uuid)?;
+        let loaded = slot.lock().expect("index lock poisoned");
+        let _idx = loaded
+            .as_ref()
+            .map(|idx| idx.write().expect("index lock poisoned"));
+
+        let mut conn = self.conn()?;
+        let tx = conn.transaction()?;
+
+        let deleted = tx.execute(
+            "DELETE FROM collections WHERE uuid = ?",
+            params![uuid.urn().to_string()],
+        )?;
+
+        if deleted == 0 {
+            return Err(DbError::CollectionNotFoundError(uuid));
+        }
+
+        tx.execute(
+            "DELETE FROM embeddings WHERE collection_uuid = ?",
+            params![uuid.urn().to_string()],
+        )?;
+        tx.commit()?;
+
+        self.index
+            .write()
+            .expect("index lock poisoned")
+            .remove(&uuid);
+        self.metadata_locks
+            .lock()
+            .expect("metadata lock poisoned")
+            .remove(&uuid);
+
+        self.remove_index_dump(uuid)
+    }
+
+    fn set_embedding_model(&self, uuid: Uuid, model: &str) -> Result<String, DbError> {
+        let lock = self.metadata_lock(uuid)?;
+        let _guard = lock.lock().expect("metadata lock poisoned");
+
+        let collection = self
+            .get_collection_by_uuid(uuid)?
+            .ok_or(DbError::CollectionNotFoundError(uuid))?;
+        if let Some(recorded) = collection.embedding_model() {
+            return Ok(recorded.to_string());
+        }
+
+        let mut metadata = collection.metadata;
+        metadata[MODEL_KEY] = model.into();
+        self.write_collection_metadata(uuid, &metadata)?;
+
+        Ok(model.to_string())
+    }
+
+    fn update_collection_metadata(
+        &self,
+        uuid: Uuid,
+        metadata: Value,
+    ) -> Result<CollectionModel, DbError> {
+        let user_metadata = validate_user_metadata(metadata)?;
+
+        let lock = self.metadata_lock(uuid)?;
+        let _guard = lock.lock().expect("metadata lock poisoned");
+
+        let mut collection = self
+            .get_collection_by_uuid(uuid)?
+            .ok_or(DbError::CollectionNotFoundError(uuid))?;
+
+        let mut metadata: Map<String, Value> = match collection.metadata {
+            Value::Object(map) => map
+                .into_iter()
+                .filter(|(key, _)| is_reserved_key(key))
+                .collect(),
+            _ => Map::new(),
+        };
+        metadata.extend(user_metadata);
+
+        collection.metadata = Value::Object(metadata);
+        self.write_collection_metadata(uuid, &collection.metadata)?;
+
+        Ok(collection)
+    }
+
     fn add_embeddings(
         &self,
         collection_uuid: Uuid,
         embeddings: Vec<EmbeddingModel>,
     ) -> Result<(), DbError> {
-        let mut stmt = self.conn.prepare("INSERT INTO embeddings (collection_uuid, uuid, embedding, metadata, text) VALUES (?,?,?,?,?)")?;
-
-        let mut index = self.index.borrow_mut();
-
-        let idx = index
-            .get_mut(&collection_uuid)
-            .expect("index does not exist for collection");
+        let shared = self.collection_index(collection_uuid)?;
+        let mut idx = shared.write().expect("index lock poisoned");
 
-        embeddings.iter().try_for_each(|e| {
-            let embedding_json =
-                serde_json::ser::to_string(&e.embedding).expect("failed to serialize vec");
-            let v = params![
-                collection_uuid.urn().to_string(),
-                e.uuid.urn().to_string(),
-                embedding_json,
-                e.metadata,
-                e.text
-            ];
-
-            idx.add(IndexEntry {
-                e: Embedding {
-                    e: e.embedding.clone(),
-                },
-                uuid: e.uuid,
-            });
-            stmt.execute(v)?;
+        self.validate_dimension(
+            collection_uuid,
+            embeddings.iter().map(|e| e.embedding.len()),
+        )?;
+        validate_index_dimension(idx.as_ref(), &embeddings)?;
 
-            Ok::<(), DbError>(())
-        })?;
+        let dimension = embeddings.first().map(|e| e.embedding.len());
+        self.insert_embeddings(collection_uuid, idx.as_mut(), embeddings)?;
 
-        Ok(())
+        match dimension {
+            Some(dimension) => self.record_dimension(collection_uuid, dimension),
+            None => Ok(()),
+        }
     }
 
     fn count_embeddings(&self, collection_uuid: Uuid) -> Result<usize, DbError> {
-        let mut stmt = self
-            .conn
-            .prepare("SELECT COUNT() FROM embeddings WHERE collection_uuid = ?")?;
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare("SELECT COUNT() FROM embeddings WHERE collection_uuid = ?")?;
 
         Ok(stmt.query_row(params![collection_uuid.urn().to_string()], |row| row.get(0))?)
     }
 
-    fn get_embeddings(&self, collection_uuid: Uuid) -> Result<Vec<EmbeddingModel>, DbError> {
-        let mut stmt = self
-            .conn
-            .prepare("SELECT * FROM embeddings WHERE collection_uuid = ?")?;
+    fn upsert_embeddings(
+        &self,
+        collection_uuid: Uuid,
+        embeddings: Vec<EmbeddingModel>,
+    ) -> Result<(), DbError> {
+        let shared = self.collection_index(collection_uuid)?;
+        let mut idx = shared.write().expect("index lock poisoned");
 
-        let mapped_rows = stmt.query_map([collection_uuid.urn().to_string()], |row| {
-            EmbeddingModel::try_from(row)
-        })?;
+        self.validate_dimension(
+            collection_uuid,
+            embeddings.iter().map(|e| e.embedding.len()),
+        )?;
+        validate_index_dimension(idx.as_ref(), &embeddings)?;
+
+        // the old rows are only gone if the new ones are stored too
+        let mut conn = self.conn()?;
+        let tx = conn.transaction()?;
+        let replaced = replace_rows(&tx, collection_uuid, &embeddings)?;
+        bump_generation(&tx, collection_uuid)?;
+        tx.commit()?;
+
+        idx.remove(&replaced);
+        let dimension = embeddings.first().map(|e| e.embedding.len());
+        add_to_index(idx.as_mut(), embeddings)?;
+        self.compact_index(collection_uuid, &mut idx)?;
+
+        match dimension {
+            Some(dimension) => self.record_dimension(collection_uuid, dimension),
+            None => Ok(()),
+        }
+    }
 
-        let mut embeddings = Vec::new();
+    fn update_metadata(
+        &self,
+        collection_uuid: Uuid,
+        uuid: Uuid,
+        metadata: Value,
+    ) -> Result<(), DbError> {
+        let metadata = Value::Object(validate_user_metadata(metadata)?);
 
-        for row in mapped_rows {
-            embeddings.push(row?);
+        let lock = self.metadata_lock(collection_uuid)?;
+        let _guard = lock.lock().expect("metadata lock poisoned");
+
+        let updated = self.conn()?.execute(
+            "UPDATE embeddings SET metadata = ? WHERE collection_uuid = ? AND uuid = ?",
+            params![
+                metadata,
+                collection_uuid.urn().to_string(),
+                uuid.urn().to_string()
+            ],
+        )?;
+
+        if updated == 0 {
+            return Err(DbError::EmbeddingNotFoundError(uuid));
         }
 
-        Ok(embeddings)
+        Ok(())
     }
 
-    fn query(
+    fn delete_embeddings(&self, collection_uuid: Uuid, uuids: &[Uuid]) -> Result<usize, DbError> {
+        if uuids.is_empty() {
+            return Ok(0);
+        }
+
+        let shared = self.collection_index(collection_uuid)?;
+        let mut idx = shared.write().expect("index lock poisoned");
+
+        let (where_clause, params) = uuids_where_clause(collection_uuid, uuids);
+
+        self.delete_matching(collection_uuid, &mut idx, &where_clause, params)
+    }
+
+    fn delete_embeddings_where(
         &self,
         collection_uuid: Uuid,
-        embeddings: &[Embedding],
-        _where: Value,
-        k: usize,
-    ) -> Result<Vec<Vec<QueryResult>>, DbError> {
-        let neighs = self.get_nearest_neighbors(collection_uuid, embeddings, _where, k)?;
+        filter: &Filter,
+    ) -> Result<usize, DbError> {
+        let shared = self.collection_index(collection_uuid)?;
+        let mut idx = shared.write().expect("index lock poisoned");
 
-        // let stmt = self.conn.prepare("SELECT * from embeddings WHERE collection_uuid = ? AND uuid = ?");
+        let mut params = vec![];
+        let where_clause = self.create_where_clause(filter, collection_uuid, &mut params);
 
-        let mut res = vec![];
+        self.delete_matching(collection_uuid, &mut idx, &where_clause, params)
+    }
 
-        for row in neighs {
-            let mut row_docs = vec![];
+    fn get_embeddings(&self, collection_uuid: Uuid) -> Result<Vec<EmbeddingModel>, DbError> {
+        self.select_embeddings(
+            "SELECT uuid, embedding, text, metadata FROM embeddings WHERE collection_uuid = ?",
+            [collection_uuid.urn().to_string()],
+        )
+    }
 
-            for (uuid, dist) in row {
-                let emb = self.get_embedding_from_uuid(uuid)?;
-                row_docs.push(QueryResult {
-                    embedding: emb.embedding,
-                    distance: dist,
-                    text: emb.text,
-                    metadata: emb.metadata,
-                    uuid,
-                });
-            }
-            res.push(row_docs)
+    fn get_embeddings_by_ids(
+        &self,
+        collection_uuid: Uuid,
+        uuids: &[Uuid],
+    ) -> Result<Vec<EmbeddingModel>, DbError> {
+        if uuids.is_empty() {
+            return Ok(vec![]);
         }
 
-        Ok(res)
+        let (where_clause, params) = uuids_where_clause(collection_uuid, uuids);
+
+        self.select_embeddings(
+            &format!("SELECT uuid, embedding, text, metadata FROM embeddings {where_clause}"),
+            params_from_iter(params),
+        )
     }
 
-    fn format_where(
+    fn get_embeddings_page(
         &self,
-        where_map: &serde_json::Map<String, serde_json::Value>,
-        result: &mut Vec<String>,
-    ) -> Result<(), DbError> {
-        if where_map.keys().next().is_none() {
-            return Ok(())
-        }
-        let operator = where_map.keys().next().unwrap().clone();
-        match operator.as_str() {
-            "$contains" => {
-                let temp = where_map.get(&operator).unwrap().as_str().unwrap();
-                result.push(format!("position(document, '{}') > 0", temp));
-            }
-            "$or" | "$and" => {
-                let mut all_subresults = vec![];
-                let subresults = where_map.get(&operator).unwrap().as_array().unwrap();
-
-                for subwhere in subresults {
-                    let mut subresult = vec![];
-                    let _ = self.format_where(subwhere.as_object().unwrap(), &mut subresult)?;
-                    all_subresults.push(subresult[0].clone());
-                }
-
-                if operator == "$or" {
-                    result.push(format!("({})", all_subresults.join(" OR ")));
-                }
+        collection_uuid: Uuid,
+        filter: &Filter,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<EmbeddingModel>, DbError> {
+        let mut params = vec![];
+        let where_clause = self.create_where_clause(filter, collection_uuid, &mut params);
+
+        params.push(SqlValue::BigInt(limit as i64));
+        params.push(SqlValue::BigInt(offset as i64));
+
+        self.select_embeddings(
+            &format!(
+                "SELECT uuid, embedding, text, metadata FROM embeddings {where_clause} ORDER BY rowid LIMIT ? OFFSET ?"
+            ),
+            params_from_iter(params),
+        )
+    }
 
-                if operator == "$and" {
-                    result.push(format!("({})", all_subresults.join(" AND ")));
-                }
-            }
-            _ => {
-                for (key, value) in where_map {
-                    match value {
-                        Value::Number(val) => {
-                            if val.is_i64() {
-                                let actual_val = val.as_i64().unwrap();
-                                result.push(format!(
-                                    " CAST(json_extract(metadata,'$.{}') AS INT) = {}",
-                                    key, actual_val
-                                ));
-                            } else if val.is_f64() {
-                                let actual_val = val.as_f64().unwrap();
-                                result.push(format!(
-                                    " CAST(json_extract(metadata,'$.{}') AS DOUBLE) = {}",
-                                    key, actual_val
-                                ));
-                            }
-                        }
-                        Value::String(val) => {
-                            result.push(format!(
-                                " json_extract_string(metadata,'$.{}') = '{}'",
-                                key, val
-                            ));
-                        }
-                        Value::Object(val) => {
-                            let (operator, operand) = val.iter().next().unwrap();
-
-                            match operator.as_str() {
-                                "$eq" => {
-                                    if let Value::String(op_str) = operand {
-                                        result.push(format!(
-                                            " json_extract_string(metadata,'$.{}') = '{}'",
-                                            key, op_str
-                                        ));
-                                    } else if let Value::Number(op_num) = operand {
-                                        result.push(format!(
-                                            " CAST(json_extract(metadata,'$.{}') AS DOUBLE) = {}",
-                                            key, op_num
-                                        ));
-                                    } else {
-                                        return Err(DbError::OperandError(format!(
-                                            "Operand {} not valid for $eq",
-                                            operand
-                                        )));
-                                    }
-                                }
-                                "$gt" => {
-                                    result.push(format!(
-                                        " CAST(json_extract(metadata,'$.{}') AS DOUBLE) > {}",
-                                        key, operand
-                                    ));
-                                }
-                                "$gte" => {
-                                    result.push(format!(
-                                        " CAST(json_extract(metadata,'$.{}') AS DOUBLE) >= {}",
-                                        key, operand
-                                    ));
-                                }
-                                "$lt" => {
-                                    result.push(format!(
-                                        " CAST(json_extract(metadata,'$.{}') AS DOUBLE) < {}",
-                                        key, operand
-                                    ));
-                                }
-                                "$lte" => {
-                                    result.push(format!(
-                                        " CAST(json_extract(metadata,'$.{}') AS DOUBLE) <= {}",
-                                        key, operand
-                                    ));
-                                }
-                                "$ne" => {
-                                    if let Value::String(op_str) = operand {
-                                        result.push(format!(
-                                            " json_extract_string(metadata,'$.{}') != '{}'",
-                                            key, op_str
-                                        ));
-                                    } else if let Value::Number(op_num) = operand {
-                                        result.push(format!(
-                                            " json_extract_string(metadata,'$.{}') != '{}'",
-                                            key, op_num
-                                        ));
-                                    } else {
-                                        return Err(DbError::OperandError(format!(
-                                            "Operand {} not valid for $ne",
-                                            operand
-                                        )));
-                                    }
-                                }
-                                _ => {
-                                    return Err(DbError::OperatorError(format!("Invalid operator: expected one of $eq, $ne, $lt, $lte, $gt, $gte, found {}", operator)));
-                                }
-                            }
-                        }
-                        Value::Array(val) => {
-                            let mut all_subresults = vec![];
-                            for subwhere in val {
-                                let mut subresults = vec![];
-                                let _ = self
-                                    .format_where(subwhere.as_object().unwrap(), &mut subresults)?;
-                                all_subresults.push(subresults[0].clone());
-                            }
-
-                            match key.as_str() {
-                                "$and" => {
-                                    result.push(format!("({})", all_subresults.join(" AND ")));
-                                }
-                                "$or" => {
-                                    result.push(format!("({})", all_subresults.join(" OR ")));
-                                }
-                                _ => {
-                                    return Err(DbError::OperatorError(format!(
-                                        "Invalid operator: expected one of $and, $or, found {}",
-                                        key
-                                    )));
-                                }
-                            }
-                        }
-                        _ => {
-                            return Err(DbError::InvalidValueError(format!("Invalid value type")));
-                        }
-                    }
+    fn query(
+        &self,
+        collection_uuid: Uuid,
+        embeddings: &[Embedding],
+        filter: &Filter,
+        k: usize,
+        options: &QueryOptions,
+    ) -> Result<Vec<Vec<QueryResult>>, DbError> {
+        self.validate_dimension(collection_uuid, embeddings.iter().map(Embedding::dim))?;
+
+        let neighs = self.get_nearest_neighbors(collection_uuid, embeddings, filter, k, options)?;
+
+        let include = options.include;
+
+        // the requested columns of the neighbours of all queries are read with one statement
+        let columns = include_columns(&include);
+        let mut fields = HashMap::new();
+        if !columns.is_empty() {
+            let uuids: Vec<Uuid> = neighs
+                .iter()
+                .flatten()
+                .map(|(uuid, _)| *uuid)
+                .collect::<HashSet<_>>()
+                .into_iter()
+                .collect();
+
+            if !uuids.is_empty() {
+                let (where_clause, params) = uuids_where_clause(collection_uuid, &uuids);
+                let conn = self.conn()?;
+                let mut stmt = conn.prepare(&format!(
+                    "SELECT uuid, {} FROM embeddings {where_clause}",
+                    columns.join(", ")
+                ))?;
+
+                for batch in stmt.query_arrow(params_from_iter(params))? {
+                    fields.extend(fields_from_batch(&batch, &include));
                 }
             }
         }
 
-        Ok(())
+        neighs
+            .into_iter()
+            .map(|row| {
+                row.into_iter()
+                    .map(|(uuid, dist)| {
+                        let mut result = QueryResult {
+                            uuid,
+                            distance: include.distances.then_some(dist),
+                            text: None,
+                            metadata: None,
+                            embedding: None,
+                        };
+
+                        if !columns.is_empty() {
+                            let stored = fields
+                                .get(&uuid)
+                                .ok_or(DbError::EmbeddingNotFoundError(uuid))?;
+                            result.text = stored.text.clone();
+                            result.metadata = stored.metadata.clone();
+                            result.embedding = stored.embedding.clone();
+                        }
+
+                        Ok(result)
+                    })
+                    .collect()
+            })
+            .collect()
     }
 
     fn reset(&self) -> Result<(), DbError> {
-        todo!()
+        self.conn()?
+            .execute_batch("DELETE FROM embeddings; DELETE FROM collections;")?;
+
+        self.index.write().expect("index lock poisoned").clear();
+        self.metadata_locks
+            .lock()
+            .expect("metadata lock poisoned")
+            .clear();
+
+        if let Some(dir) = self.index_dir() {
+            if dir.exists() {
+                std::fs::remove_dir_all(dir).map_err(IndexError::from)?;
+            }
+        }
+
+        Ok(())
     }
 
     fn persist(&self) -> Result<(), DbError> {
-        todo!()
+        if self.path.is_none() {
+            return Ok(());
+        }
+
+        self.conn()?.execute_batch("CHECKPOINT;")?;
+
+        if let Some(dir) = self.index_dir() {
+            std::fs::create_dir_all(&dir).map_err(IndexError::from)?;
+
+            let uuids: Vec<Uuid> = self
+                .index
+                .read()
+                .expect("index lock poisoned")
+                .keys()
+                .copied()
+                .collect();
+
+            // collections that were never used have nothing to dump
+            for collection_uuid in uuids {
+                let Some(shared) = self.loaded_index(collection_uuid) else {
+                    continue;
+                };
+                // writers bump the generation under the write lock, so it matches the index here
+                let idx = shared.read().expect("index lock poisoned");
+                if !idx.is_empty() {
+                    let basename = dir.join(collection_uuid.simple().to_string());
+                    let generation = collection_generation(&self.conn()?, collection_uuid)?;
+                    idx.dump(&basename)?;
+                    std::fs::write(generation_path(&basename), generation.to_string())
+                        .map_err(IndexError::from)?;
+                }
+            }
+        }
+
+        Ok(())
     }
 }
 
@@ -496,39 +1159,433 @@ impl TryFrom<&duckdb::Row<'_>> for CollectionModel {
     }
 }
 
-impl TryFrom<&duckdb::Row<'_>> for EmbeddingModel {
-    type Error = duckdb::Error;
+// Compiles `filter` into a SQL boolean expression over the `embeddings` table. Keys and values
+// are never spliced into the SQL, they are appended to `params` and bound by the caller.
+fn compile_filter(filter: &Filter, params: &mut Vec<SqlValue>) -> String {
+    fn json_path(key: &str) -> SqlValue {
+        SqlValue::Text(format!("$.\"{}\"", key.replace('"', "\\\"")))
+    }
 
-    // "CREATE TABLE embeddings (collection_uuid STRING, uuid STRING, embedding JSON, text STRING, metadata STRING)",
-    fn try_from(row: &duckdb::Row<'_>) -> Result<Self, Self::Error> {
-        let embedding_json: String = row.get(2)?;
-        let embedding =
-            serde_json::from_str(&embedding_json).expect("failed to deserialize vec from db");
+    fn compare(key: &str, op: &str, value: &FilterValue, params: &mut Vec<SqlValue>) -> String {
+        params.push(json_path(key));
+
+        let (column, value) = match value {
+            FilterValue::String(s) => (
+                "json_extract_string(metadata, ?)",
+                SqlValue::Text(s.clone()),
+            ),
+            FilterValue::Number(n) => (
+                "TRY_CAST(json_extract(metadata, ?) AS DOUBLE)",
+                SqlValue::Double(*n),
+            ),
+            FilterValue::Bool(b) => (
+                "TRY_CAST(json_extract(metadata, ?) AS BOOLEAN)",
+                SqlValue::Boolean(*b),
+            ),
+        };
+        params.push(value);
 
-        println!("here");
+        format!("{column} {op} ?")
+    }
 
-        let model = EmbeddingModel {
-            uuid: row
-                .get::<_, String>(1)?
-                .parse()
-                .expect("failed to read uuid from db"),
-            embedding,
-            text: row.get(3)?,
-            metadata: row.get(4)?,
-        };
+    fn join(filters: &[Filter], sep: &str, empty: &str, params: &mut Vec<SqlValue>) -> String {
+        if filters.is_empty() {
+            return empty.to_string();
+        }
+
+        let clauses: Vec<_> = filters.iter().map(|f| compile_filter(f, params)).collect();
+        format!("({})", clauses.join(sep))
+    }
+
+    match filter {
+        Filter::All => "TRUE".to_string(),
+        Filter::Eq(key, value) => compare(key, "=", value, params),
+        Filter::Ne(key, value) => compare(key, "!=", value, params),
+        Filter::Gt(key, value) => compare(key, ">", &FilterValue::Number(*value), params),
+        Filter::Gte(key, value) => compare(key, ">=", &FilterValue::Number(*value), params),
+        Filter::Lt(key, value) => compare(key, "<", &FilterValue::Number(*value), params),
+        Filter::Lte(key, value) => compare(key, "<=", &FilterValue::Number(*value), params),
+        Filter::In(key, values) => {
+            let eqs: Vec<_> = values
+                .iter()
+                .map(|v| Filter::Eq(key.clone(), v.clone()))
+                .collect();
+            join(&eqs, " OR ", "FALSE", params)
+        }
+        Filter::Nin(key, values) => {
+            let nes: Vec<_> = values
+                .iter()
+                .map(|v| Filter::Ne(key.clone(), v.clone()))
+                .collect();
+            join(&nes, " AND ", "TRUE", params)
+        }
+        Filter::Contains(text) => {
+            params.push(SqlValue::Text(text.clone()));
+            "contains(text, ?)".to_string()
+        }
+        Filter::And(filters) => join(filters, " AND ", "TRUE", params),
+        Filter::Or(filters) => join(filters, " OR ", "FALSE", params),
+        Filter::Not(filter) => format!("(NOT {})", compile_filter(filter, params)),
+    }
+}
+
+// Appends rows for `embeddings` to the `embeddings` table. The rows are passed as one arrow
+// batch, so vectors are stored as `FLOAT[]` without being serialized.
+fn append_embeddings(
+    conn: &Connection,
+    collection_uuid: Uuid,
+    embeddings: &[EmbeddingModel],
+) -> Result<(), DbError> {
+    if embeddings.is_empty() {
+        return Ok(());
+    }
+
+    let collection_uuid = collection_uuid.urn().to_string();
+    let columns: Vec<(&str, ArrayRef)> = vec![
+        (
+            "collection_uuid",
+            Arc::new(StringArray::from(vec![
+                collection_uuid.as_str();
+                embeddings.len()
+            ])),
+        ),
+        (
+            "uuid",
+            Arc::new(
+                embeddings
+                    .iter()
+                    .map(|e| Some(e.uuid.urn().to_string()))
+                    .collect::<StringArray>(),
+            ),
+        ),
+        (
+            "embedding",
+            Arc::new(ListArray::from_iter_primitive::<Float32Type, _, _>(
+                embeddings
+                    .iter()
+                    .map(|e| Some(e.embedding.iter().copied().map(Some))),
+            )),
+        ),
+        (
+            "text",
+            Arc::new(
+                embeddings
+                    .iter()
+                    .map(|e| Some(e.text.as_str()))
+                    .collect::<StringArray>(),
+            ),
+        ),
+        (
+            "metadata",
+            Arc::new(
+                embeddings
+                    .iter()
+                    .map(|e| Some(e.metadata.to_string()))
+                    .collect::<StringArray>(),
+            ),
+        ),
+    ];
+    let batch = RecordBatch::try_from_iter(columns).map_err(|e| DbError::SqlError(e.into()))?;
+
+    conn.appender("embeddings")?.append_record_batch(batch)?;
+
+    Ok(())
+}
+
+fn select_uuids(
+    conn: &Connection,
+    where_clause: &str,
+    params: Vec<SqlValue>,
+) -> Result<HashSet<Uuid>, DbError> {
+    let mut stmt = conn.prepare(&format!("SELECT uuid FROM embeddings {where_clause}"))?;
+    let mapped_rows = stmt.query_map(params_from_iter(params), |row| row.get::<_, String>(0))?;
+
+    let mut uuids = HashSet::new();
+    for row in mapped_rows {
+        uuids.insert(row?.parse().expect("failed to parse uuid from string"));
+    }
+
+    Ok(uuids)
+}
+
+// Deletes the rows matching `where_clause`. Returns the uuids of the deleted rows.
+fn delete_rows(
+    conn: &Connection,
+    where_clause: &str,
+    params: Vec<SqlValue>,
+) -> Result<HashSet<Uuid>, DbError> {
+    let uuids = select_uuids(conn, where_clause, params.clone())?;
+    if !uuids.is_empty() {
+        conn.execute(
+            &format!("DELETE FROM embeddings {where_clause}"),
+            params_from_iter(params),
+        )?;
+    }
+
+    Ok(uuids)
+}
+
+// Stores `embeddings`, replacing the rows with the same uuids. Returns the uuids of the
+// replaced rows.
+fn replace_rows(
+    conn: &Connection,
+    collection_uuid: Uuid,
+    embeddings: &[EmbeddingModel],
+) -> Result<HashSet<Uuid>, DbError> {
+    if embeddings.is_empty() {
+        return Ok(HashSet::new());
+    }
 
-        Ok(model)
+    let uuids: Vec<_> = embeddings.iter().map(|e| e.uuid).collect();
+    let (where_clause, params) = uuids_where_clause(collection_uuid, &uuids);
+    let replaced = delete_rows(conn, &where_clause, params)?;
+
+    append_embeddings(conn, collection_uuid, embeddings)?;
+
+    Ok(replaced)
+}
+
+// Checks that the index accepts `embeddings` before their rows are committed, so that adding
+// them to the index after the commit cannot fail on their dimension.
+fn validate_index_dimension(
+    idx: &dyn VectorIndex,
+    embeddings: &[EmbeddingModel],
+) -> Result<(), DbError> {
+    let Some(expected) = idx
+        .dimension()
+        .or_else(|| embeddings.first().map(|e| e.embedding.len()))
+    else {
+        return Ok(());
+    };
+
+    match embeddings.iter().find(|e| e.embedding.len() != expected) {
+        Some(e) => Err(DbError::DimensionError {
+            expected,
+            found: e.embedding.len(),
+        }),
+        None => Ok(()),
+    }
+}
+
+fn add_to_index(idx: &mut dyn VectorIndex, embeddings: Vec<EmbeddingModel>) -> Result<(), DbError> {
+    for e in embeddings {
+        idx.add(IndexEntry {
+            e: e.embedding.into(),
+            uuid: e.uuid,
+        })?;
     }
+
+    Ok(())
+}
+
+fn uuids_where_clause(collection_uuid: Uuid, uuids: &[Uuid]) -> (String, Vec<SqlValue>) {
+    let (in_clause, uuid_params) = uuids_in_clause(uuids);
+
+    let mut params = vec![SqlValue::Text(collection_uuid.urn().to_string())];
+    params.extend(uuid_params);
+
+    (format!("WHERE collection_uuid = ? AND {in_clause}"), params)
+}
+
+fn uuids_in_clause(uuids: &[Uuid]) -> (String, Vec<SqlValue>) {
+    let params = uuids
+        .iter()
+        .map(|uuid| SqlValue::Text(uuid.urn().to_string()))
+        .collect();
+
+    let placeholders = vec!["?"; uuids.len()].join(", ");
+
+    (format!("uuid IN ({placeholders})"), params)
+}
+
+fn exact_nearest_neighbors(
+    idx: &dyn VectorIndex,
+    vectors: &[(Uuid, Vec<f32>)],
+    embedding: &Embedding,
+    k: usize,
+) -> Vec<(Uuid, f32)> {
+    let mut neighs: Vec<_> = vectors
+        .iter()
+        .map(|(uuid, v)| (*uuid, idx.distance(embedding.get(), v)))
+        .collect();
+
+    neighs.sort_by(|a, b| a.1.total_cmp(&b.1));
+    neighs.truncate(k);
+
+    neighs
+}
+
+// Reads rows of `SELECT uuid, embedding FROM embeddings`.
+fn vectors_from_batch(batch: &RecordBatch) -> Vec<(Uuid, Vec<f32>)> {
+    let uuids = batch
+        .column(0)
+        .as_any()
+        .downcast_ref::<StringArray>()
+        .expect("expected a string column");
+    let vectors = batch
+        .column(1)
+        .as_any()
+        .downcast_ref::<ListArray>()
+        .expect("expected a list column");
+
+    (0..batch.num_rows())
+        .map(|i| {
+            let vector = vectors.value(i);
+            let vector = vector
+                .as_any()
+                .downcast_ref::<Float32Array>()
+                .expect("expected a float column");
+
+            (
+                uuids.value(i).parse().expect("failed to read uuid from db"),
+                vector.values().to_vec(),
+            )
+        })
+        .collect()
+}
+
+// Columns selected to return the fields in `include`, in the order `fields_from_batch` reads
+// them.
+fn include_columns(include: &Include) -> Vec<&'static str> {
+    [
+        (include.texts, "text"),
+        (include.metadatas, "metadata"),
+        (include.embeddings, "embedding"),
+    ]
+    .into_iter()
+    .filter_map(|(included, column)| included.then_some(column))
+    .collect()
+}
+
+// Reads rows of `SELECT uuid, <include_columns> FROM embeddings` into partial results keyed by
+// uuid. Only the uuid, text, metadata and embedding of the results are set.
+fn fields_from_batch(batch: &RecordBatch, include: &Include) -> Vec<(Uuid, QueryResult)> {
+    let mut columns = batch.columns().iter();
+
+    let uuids = columns
+        .next()
+        .and_then(|c| c.as_any().downcast_ref::<StringArray>())
+        .expect("expected a string column");
+    let mut string_column = |included: bool| {
+        included.then(|| {
+            columns
+                .next()
+                .and_then(|c| c.as_any().downcast_ref::<StringArray>())
+                .expect("expected a string column")
+        })
+    };
+    let texts = string_column(include.texts);
+    let metadata = string_column(include.metadatas);
+    let vectors = include.embeddings.then(|| {
+        columns
+            .next()
+            .and_then(|c| c.as_any().downcast_ref::<ListArray>())
+            .expect("expected a list column")
+    });
+
+    (0..batch.num_rows())
+        .map(|i| {
+            let uuid = uuids.value(i).parse().expect("failed to read uuid from db");
+            let embedding = vectors.map(|vectors| {
+                let vector = vectors.value(i);
+                vector
+                    .as_any()
+                    .downcast_ref::<Float32Array>()
+                    .expect("expected a float column")
+                    .values()
+                    .to_vec()
+            });
+
+            let result = QueryResult {
+                uuid,
+                distance: None,
+                text: texts.map(|texts| texts.value(i).to_string()),
+                metadata: metadata.map(|metadata| {
+                    serde_json::from_str(metadata.value(i))
+                        .expect("failed to read metadata from db")
+                }),
+                embedding,
+            };
+
+            (uuid, result)
+        })
+        .collect()
+}
+
+// Reads rows of `SELECT uuid, embedding, text, metadata FROM embeddings`. The `FLOAT[]`
+// column can only be read through the arrow interface.
+fn embeddings_from_batch(batch: &RecordBatch) -> Vec<EmbeddingModel> {
+    let string_column = |i: usize| {
+        batch
+            .column(i)
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .expect("expected a string column")
+    };
+
+    let uuids = string_column(0);
+    let vectors = batch
+        .column(1)
+        .as_any()
+        .downcast_ref::<ListArray>()
+        .expect("expected a list column");
+    let texts = string_column(2);
+    let metadata = string_column(3);
+
+    (0..batch.num_rows())
+        .map(|i| {
+            let vector = vectors.value(i);
+            let vector = vector
+                .as_any()
+                .downcast_ref::<Float32Array>()
+                .expect("expected a float column");
+
+            EmbeddingModel {
+                uuid: uuids.value(i).parse().expect("failed to read uuid from db"),
+                embedding: vector.values().to_vec(),
+                text: texts.value(i).to_string(),
+                metadata: serde_json::from_str(metadata.value(i))
+                    .expect("failed to read metadata from db"),
+            }
+        })
+        .collect()
 }
 
 #[cfg(test)]
 mod tests {
+    use std::path::{Path, PathBuf};
+
     use uuid::Uuid;
 
-    use crate::db::{model::EmbeddingModel, Db};
+    use crate::{
+        db::{model::EmbeddingModel, Db, DbError},
+        filter::Filter,
+        index::{random_vectors, IndexConfig, IndexKind, Metric},
+        Include, QueryOptions,
+    };
 
     use super::DuckDB;
 
+    // A document with the given embedding and placeholder text and metadata.
+    fn embedding_model(embedding: Vec<f32>) -> EmbeddingModel {
+        EmbeddingModel {
+            embedding,
+            uuid: Uuid::new_v4(),
+            metadata: serde_json::json!({"id": "102"}),
+            text: "hello, this is a sentence".to_string(),
+        }
+    }
+
+    fn temp_db_path() -> PathBuf {
+        std::env::temp_dir().join(format!("cedar-{}.duckdb", Uuid::new_v4()))
+    }
+
+    // Removes a database file created by a test, along with its index dumps.
+    fn remove_db_files(path: &Path) {
+        std::fs::remove_file(path).unwrap();
+        std::fs::remove_dir_all(format!("{}.index", path.display())).unwrap();
+    }
+
     #[test]
     pub fn test_create_collection() {
         let db = DuckDB::new(Default::default()).unwrap();
@@ -536,7 +1593,7 @@ mod tests {
 
         let name = "collection1";
 
-        let collection_create = db.create_collection(name).unwrap();
+        let collection_create = db.create_collection(name, serde_json::json!({})).unwrap();
 
         let collection_get = db.get_collection(name).unwrap().unwrap();
 
@@ -550,8 +1607,14 @@ mod tests {
 
         let mut collections = vec![];
 
-        collections.push(db.create_collection("collection1").unwrap());
-        collections.push(db.create_collection("collection2").unwrap());
+        collections.push(
+            db.create_collection("collection1", serde_json::json!({}))
+                .unwrap(),
+        );
+        collections.push(
+            db.create_collection("collection2", serde_json::json!({}))
+                .unwrap(),
+        );
 
         let mut listed_collections = db.list_collections().unwrap();
 
@@ -568,7 +1631,10 @@ mod tests {
 
         let name = "collection1";
 
-        let collection_create_uuid = db.create_collection(name).unwrap().uuid;
+        let collection_create_uuid = db
+            .create_collection(name, serde_json::json!({}))
+            .unwrap()
+            .uuid;
 
         let collection_get_uuid = db.get_collection(name).unwrap().unwrap().uuid;
 
@@ -582,7 +1648,10 @@ mod tests {
 
         let name = "collection1";
 
-        let collection_create_uuid = db.create_collection(name).unwrap().uuid;
+        let collection_create_uuid = db
+            .create_collection(name, serde_json::json!({}))
+            .unwrap()
+            .uuid;
         let new_name = "new_collection1";
 
         let updated_collection = db
@@ -593,42 +1662,153 @@ mod tests {
         assert_eq!(new_name, updated_name);
     }
 
+    #[test]
+    pub fn test_delete_collection() {
+        let db = DuckDB::new(Default::default()).unwrap();
+        db.init().unwrap();
+
+        let collection = db
+            .create_collection("collection1", serde_json::json!({}))
+            .unwrap();
+        let other = db
+            .create_collection("collection2", serde_json::json!({}))
+            .unwrap();
+
+        let e_model = embedding_model(vec![0.0; 384]);
+        db.add_embeddings(collection.uuid, vec![e_model.clone()])
+            .unwrap();
+        db.add_embeddings(other.uuid, vec![e_model]).unwrap();
+
+        db.delete_collection(collection.uuid).unwrap();
+
+        assert!(db.get_collection("collection1").unwrap().is_none());
+        assert_eq!(0, db.count_embeddings(collection.uuid).unwrap());
+        assert_eq!(1, db.count_embeddings(other.uuid).unwrap());
+        assert!(!db.index.read().unwrap().contains_key(&collection.uuid));
+
+        assert!(db.delete_collection(collection.uuid).is_err());
+    }
+
     #[test]
     pub fn test_add_embeddings() {
         let db = DuckDB::new(Default::default()).unwrap();
 
         db.init().unwrap();
 
-        let collection = db.create_collection("collection1").unwrap();
+        let collection = db
+            .create_collection("collection1", serde_json::json!({}))
+            .unwrap();
         let collection_uuid = collection.uuid;
 
-        let e_model = EmbeddingModel {
-            embedding: vec![0.0; 384],
-            uuid: Uuid::new_v4(),
-            metadata: serde_json::json!({"id": "102"}),
-            text: "hello, this is a sentence".to_string(),
-        };
+        let e_model = embedding_model(vec![0.0; 384]);
 
         db.add_embeddings(collection_uuid, vec![e_model]).unwrap();
     }
 
+    #[test]
+    pub fn test_index_dimension_mismatch_writes_nothing() {
+        let db = DuckDB::new(Default::default()).unwrap();
+        db.init().unwrap();
+
+        let collection = db
+            .create_collection("collection1", serde_json::json!({}))
+            .unwrap();
+        db.add_embeddings(collection.uuid, vec![embedding_model(vec![1.0; 3])])
+            .unwrap();
+
+        // the table no longer tells the dimension, the index still holds 3 dimensional points
+        db.conn()
+            .unwrap()
+            .execute_batch("DELETE FROM embeddings;")
+            .unwrap();
+        let mut metadata = db
+            .get_collection_by_uuid(collection.uuid)
+            .unwrap()
+            .unwrap()
+            .metadata;
+        metadata
+            .as_object_mut()
+            .unwrap()
+            .remove(super::DIMENSION_KEY);
+        db.write_collection_metadata(collection.uuid, &metadata)
+            .unwrap();
+
+        for res in [
+            db.add_embeddings(collection.uuid, vec![embedding_model(vec![1.0; 2])]),
+            db.upsert_embeddings(collection.uuid, vec![embedding_model(vec![1.0; 2])]),
+        ] {
+            assert!(matches!(
+                res,
+                Err(DbError::DimensionError {
+                    expected: 3,
+                    found: 2
+                })
+            ));
+        }
+        assert_eq!(db.count_embeddings(collection.uuid).unwrap(), 0);
+    }
+
+    #[test]
+    pub fn test_collection_dimension_and_model() {
+        let db = DuckDB::new(Default::default()).unwrap();
+        db.init().unwrap();
+
+        let collection = db
+            .create_collection("collection1", serde_json::json!({}))
+            .unwrap();
+        let collection_uuid = collection.uuid;
+        assert_eq!(collection.dimension(), None);
+
+        let e_model = |dim| embedding_model(vec![1.0; dim]);
+
+        db.add_embeddings(collection_uuid, vec![e_model(384)])
+            .unwrap();
+        let collection = db.get_collection("collection1").unwrap().unwrap();
+        assert_eq!(collection.dimension(), Some(384));
+
+        // the recorded dimension holds even once the collection is emptied
+        db.delete_embeddings_where(collection_uuid, &Filter::All)
+            .unwrap();
+        assert!(matches!(
+            db.add_embeddings(collection_uuid, vec![e_model(1536)]),
+            Err(DbError::DimensionError {
+                expected: 384,
+                found: 1536
+            })
+        ));
+        assert!(matches!(
+            db.upsert_embeddings(collection_uuid, vec![e_model(1536)]),
+            Err(DbError::DimensionError { .. })
+        ));
+        assert_eq!(db.count_embeddings(collection_uuid).unwrap(), 0);
+
+        assert_eq!(
+            db.set_embedding_model(collection_uuid, "model-a").unwrap(),
+            "model-a"
+        );
+        assert_eq!(
+            db.set_embedding_model(collection_uuid, "model-b").unwrap(),
+            "model-a"
+        );
+        let collection = db.get_collection_by_uuid(collection_uuid).unwrap().unwrap();
+        assert_eq!(collection.embedding_model(), Some("model-a"));
+        assert_eq!(collection.dimension(), Some(384));
+    }
+
     #[test]
     pub fn test_count_embeddings() {
         let db = DuckDB::new(Default::default()).unwrap();
 
         db.init().unwrap();
 
-        let collection = db.create_collection("collection1").unwrap();
+        let collection = db
+            .create_collection("collection1", serde_json::json!({}))
+            .unwrap();
         let collection_uuid = collection.uuid;
 
         assert_eq!(0, db.count_embeddings(collection_uuid).unwrap());
 
-        let e_model = EmbeddingModel {
-            embedding: vec![0.0; 384],
-            uuid: Uuid::new_v4(),
-            metadata: serde_json::json!({"id": "102"}),
-            text: "hello, this is a sentence".to_string(),
-        };
+        let e_model = embedding_model(vec![0.0; 384]);
 
         db.add_embeddings(collection_uuid, vec![e_model]).unwrap();
 
@@ -641,17 +1821,14 @@ mod tests {
 
         db.init().unwrap();
 
-        let collection = db.create_collection("collection1").unwrap();
+        let collection = db
+            .create_collection("collection1", serde_json::json!({}))
+            .unwrap();
         let collection_uuid = collection.uuid;
 
         assert!(db.get_embeddings(collection_uuid).unwrap().is_empty());
 
-        let e_model = EmbeddingModel {
-            embedding: vec![0.0; 384],
-            uuid: Uuid::new_v4(),
-            metadata: serde_json::json!({"id": "102"}),
-            text: "hello, this is a sentence".to_string(),
-        };
+        let e_model = embedding_model(vec![0.0; 384]);
 
         db.add_embeddings(collection_uuid, vec![e_model.clone()])
             .unwrap();
@@ -661,6 +1838,1144 @@ mod tests {
         assert_eq!(embeddings[0], e_model);
     }
 
+    #[test]
+    pub fn test_persist_and_reopen() {
+        let path = temp_db_path();
+
+        let e_model = embedding_model(vec![0.0; 384]);
+
+        let collection_uuid = {
+            let db = DuckDB::open(&path, Default::default()).unwrap();
+            db.init().unwrap();
+
+            let collection = db
+                .create_collection("collection1", serde_json::json!({}))
+                .unwrap();
+            db.add_embeddings(collection.uuid, vec![e_model.clone()])
+                .unwrap();
+            db.persist().unwrap();
+
+            collection.uuid
+        };
+
+        let db = DuckDB::open(&path, Default::default()).unwrap();
+        db.init().unwrap();
+
+        let collection = db.get_collection("collection1").unwrap().unwrap();
+        assert_eq!(collection.uuid, collection_uuid);
+        assert_eq!(db.get_embeddings(collection_uuid).unwrap(), vec![e_model]);
+
+        drop(db);
+        remove_db_files(&path);
+    }
+
+    #[test]
+    pub fn test_query_reopened_collection() {
+        let path = temp_db_path();
+
+        let uuid = Uuid::new_v4();
+        let mut embedding = vec![0.0; 384];
+        embedding[0] = 1.0;
+
+        {
+            let db = DuckDB::open(&path, Default::default()).unwrap();
+            db.init().unwrap();
+
+            let collection = db
+                .create_collection("collection1", serde_json::json!({}))
+                .unwrap();
+            let e_model = EmbeddingModel {
+                uuid,
+                ..embedding_model(embedding.clone())
+            };
+            db.add_embeddings(collection.uuid, vec![e_model]).unwrap();
+            db.persist().unwrap();
+        }
+
+        let db = DuckDB::open(&path, Default::default()).unwrap();
+        db.init().unwrap();
+
+        let collection = db.get_collection("collection1").unwrap().unwrap();
+        let res = db
+            .query(
+                collection.uuid,
+                &[embedding.into()],
+                &Filter::All,
+                1,
+                &QueryOptions::default(),
+            )
+            .unwrap();
+
+        assert_eq!(res[0][0].uuid, uuid);
+
+        drop(db);
+        remove_db_files(&path);
+    }
+
+    #[test]
+    pub fn test_stale_index_dump_is_rebuilt() {
+        let path = temp_db_path();
+
+        let make_model = |x: f32| {
+            let mut embedding = vec![0.0; 384];
+            embedding[0] = x;
+            embedding[1] = 1.0 - x;
+            embedding_model(embedding)
+        };
+        let first = make_model(0.0);
+        let second = make_model(1.0);
+
+        {
+            let db = DuckDB::open(&path, Default::default()).unwrap();
+            db.init().unwrap();
+
+            let collection = db
+                .create_collection("collection1", serde_json::json!({}))
+                .unwrap();
+            db.add_embeddings(collection.uuid, vec![first]).unwrap();
+            db.persist().unwrap();
+
+            // written after the index dump, so the dump no longer matches the table
+            db.add_embeddings(collection.uuid, vec![second.clone()])
+                .unwrap();
+            db.conn().unwrap().execute_batch("CHECKPOINT;").unwrap();
+        }
+
+        let db = DuckDB::open(&path, Default::default()).unwrap();
+        db.init().unwrap();
+
+        let collection = db.get_collection("collection1").unwrap().unwrap();
+        let res = db
+            .query(
+                collection.uuid,
+                &[second.embedding.clone().into()],
+                &Filter::All,
+                1,
+                &QueryOptions::default(),
+            )
+            .unwrap();
+
+        assert_eq!(res[0][0].uuid, second.uuid);
+
+        drop(db);
+        remove_db_files(&path);
+    }
+
+    #[test]
+    pub fn test_index_dump_of_replaced_vectors_is_rebuilt() {
+        let path = temp_db_path();
+
+        let mut e_model = embedding_model(vec![0.0; 384]);
+        e_model.embedding[0] = 1.0;
+
+        {
+            let db = DuckDB::open(&path, Default::default()).unwrap();
+            db.init().unwrap();
+
+            let collection = db
+                .create_collection("collection1", serde_json::json!({}))
+                .unwrap();
+            db.add_embeddings(collection.uuid, vec![e_model.clone()])
+                .unwrap();
+            db.persist().unwrap();
+
+            // same uuids as the dump, different vector
+            e_model.embedding = vec![0.0; 384];
+            e_model.embedding[1] = 1.0;
+            db.upsert_embeddings(collection.uuid, vec![e_model.clone()])
+                .unwrap();
+            db.conn().unwrap().execute_batch("CHECKPOINT;").unwrap();
+        }
+
+        let db = DuckDB::open(&path, Default::default()).unwrap();
+        db.init().unwrap();
+
+        let collection = db.get_collection("collection1").unwrap().unwrap();
+        let res = db
+            .query(
+                collection.uuid,
+                &[e_model.embedding.clone().into()],
+                &Filter::All,
+                1,
+                &QueryOptions::default(),
+            )
+            .unwrap();
+
+        assert!(res[0][0].distance.unwrap().abs() < 1e-5);
+
+        drop(db);
+        remove_db_files(&path);
+    }
+
+    #[test]
+    pub fn test_migrate_json_embeddings() {
+        let db = DuckDB::new(Default::default()).unwrap();
+
+        db.init_collections_table().unwrap();
+        db.conn()
+            .unwrap()
+            .execute_batch(
+                "CREATE TABLE embeddings (collection_uuid STRING, uuid STRING, embedding JSON, text STRING, metadata JSON);",
+            )
+            .unwrap();
+
+        let collection = db
+            .create_collection("collection1", serde_json::json!({}))
+            .unwrap();
+        let e_model = embedding_model(vec![0.5; 384]);
+        db.conn()
+            .unwrap()
+            .execute(
+                "INSERT INTO embeddings VALUES (?, ?, ?, ?, ?)",
+                duckdb::params![
+                    collection.uuid.urn().to_string(),
+                    e_model.uuid.urn().to_string(),
+                    serde_json::to_string(&e_model.embedding).unwrap(),
+                    e_model.text,
+                    e_model.metadata,
+                ],
+            )
+            .unwrap();
+
+        db.init().unwrap();
+
+        assert_eq!(db.get_embeddings(collection.uuid).unwrap(), vec![e_model]);
+    }
+
+    #[test]
+    pub fn test_query_filter() {
+        let db = DuckDB::new(Default::default()).unwrap();
+        db.init().unwrap();
+
+        let collection = db
+            .create_collection("collection1", serde_json::json!({}))
+            .unwrap();
+
+        let models: Vec<_> = ["o'reilly", "' OR '1'='1", "manning"]
+            .iter()
+            .enumerate()
+            .map(|(i, source)| {
+                let mut embedding = vec![0.0; 384];
+                embedding[i] = 1.0;
+                EmbeddingModel {
+                    embedding,
+                    uuid: Uuid::new_v4(),
+                    metadata: serde_json::json!({ "source": source, "year": 2020 + i }),
+                    text: format!("document {i}"),
+                }
+            })
+            .collect();
+        db.add_embeddings(collection.uuid, models.clone()).unwrap();
+
+        let query = |filter: Filter| {
+            let res = db
+                .query(
+                    collection.uuid,
+                    &[vec![1.0; 384].into()],
+                    &filter,
+                    3,
+                    &QueryOptions::default(),
+                )
+                .unwrap();
+            let mut uuids: Vec<_> = res[0].iter().map(|r| r.uuid).collect();
+            uuids.sort();
+            uuids
+        };
+
+        assert_eq!(
+            query(Filter::eq("source", "o'reilly")),
+            vec![models[0].uuid]
+        );
+        assert_eq!(
+            query(Filter::eq("source", "' OR '1'='1")),
+            vec![models[1].uuid]
+        );
+
+        let mut expected = vec![models[1].uuid, models[2].uuid];
+        expected.sort();
+        assert_eq!(query(Filter::gte("year", 2021)), expected);
+        assert_eq!(
+            query(!Filter::is_in("source", ["o'reilly", "nobody"])),
+            expected
+        );
+    }
+
+    #[test]
+    pub fn test_query_filter_mixed_types() {
+        let db = DuckDB::new(Default::default()).unwrap();
+        db.init().unwrap();
+
+        let collection = db
+            .create_collection("collection1", serde_json::json!({}))
+            .unwrap();
+
+        let models: Vec<_> = [
+            serde_json::json!({ "n": "abc", "flag": "yes" }),
+            serde_json::json!({ "n": 2, "flag": true }),
+        ]
+        .into_iter()
+        .map(|metadata| EmbeddingModel {
+            uuid: Uuid::new_v4(),
+            metadata,
+            ..embedding_model(vec![1.0; 384])
+        })
+        .collect();
+        db.add_embeddings(collection.uuid, models.clone()).unwrap();
+
+        // values that do not convert to the operand's type never match, they do not fail
+        for filter in [
+            Filter::try_from(serde_json::json!({ "n": { "$gt": 1 } })).unwrap(),
+            Filter::eq("flag", true),
+        ] {
+            let res = db
+                .query(
+                    collection.uuid,
+                    &[vec![1.0; 384].into()],
+                    &filter,
+                    2,
+                    &QueryOptions::default(),
+                )
+                .unwrap();
+
+            assert_eq!(res[0].len(), 1);
+            assert_eq!(res[0][0].uuid, models[1].uuid);
+        }
+    }
+
+    #[test]
+    pub fn test_query_selective_filter_returns_k() {
+        let db = DuckDB::new(Default::default()).unwrap();
+        db.init().unwrap();
+
+        let collection = db
+            .create_collection("collection1", serde_json::json!({}))
+            .unwrap();
+
+        // the rare documents point away from the query, so unfiltered HNSW never reaches them
+        let models: Vec<_> = (0..2000)
+            .map(|i| {
+                let rare = i % 200 == 0;
+                let x = i as f32 / 2000.0;
+                EmbeddingModel {
+                    embedding: if rare {
+                        vec![-1.0, x, 0.0, 0.0]
+                    } else {
+                        vec![1.0, x, 0.0, 0.0]
+                    },
+                    uuid: Uuid::new_v4(),
+                    metadata: serde_json::json!({ "rare": rare }),
+                    text: format!("document {i}"),
+                }
+            })
+            .collect();
+        db.add_embeddings(collection.uuid, models).unwrap();
+
+        let res = db
+            .query(
+                collection.uuid,
+                &[vec![1.0, 0.0, 0.0, 0.0].into()],
+                &Filter::eq("rare", true),
+                5,
+                &QueryOptions::default(),
+            )
+            .unwrap();
+
+        assert_eq!(res[0].len(), 5);
+        assert!(res[0]
+            .iter()
+            .all(|r| r.metadata == Some(serde_json::json!({ "rare": true }))));
+
+        let res = db
+            .query(
+                collection.uuid,
+                &[vec![1.0, 0.0, 0.0, 0.0].into()],
+                &Filter::eq("rare", false),
+                5,
+                &QueryOptions::default(),
+            )
+            .unwrap();
+
+        assert_eq!(res[0].len(), 5);
+    }
+
+    #[test]
+    pub fn test_query_batch() {
+        let db = DuckDB::new(Default::default()).unwrap();
+        db.init().unwrap();
+
+        let collection = db
+            .create_collection("collection1", serde_json::json!({}))
+            .unwrap();
+
+        let models: Vec<_> = (0..4)
+            .map(|i| {
+                let mut embedding = vec![0.0; 4];
+                embedding[i] = 1.0;
+                EmbeddingModel {
+                    embedding,
+                    uuid: Uuid::new_v4(),
+                    metadata: serde_json::json!({ "i": i }),
+                    text: format!("document {i}"),
+                }
+            })
+            .collect();
+        db.add_embeddings(collection.uuid, models.clone()).unwrap();
+
+        // the same neighbours are shared by several queries of the batch
+        let res = db
+            .query(
+                collection.uuid,
+                &[
+                    vec![1.0, 0.0, 0.0, 0.0].into(),
+                    vec![0.0, 0.0, 0.0, 1.0].into(),
+                    vec![1.0, 0.0, 0.0, 0.0].into(),
+                ],
+                &Filter::All,
+                2,
+                &QueryOptions::default(),
+            )
+            .unwrap();
+
+        assert_eq!(res.len(), 3);
+        assert_eq!(res[0][0].uuid, models[0].uuid);
+        assert_eq!(res[0][0].text, Some(models[0].text.clone()));
+        assert_eq!(res[1][0].metadata, Some(models[3].metadata.clone()));
+        assert_eq!(
+            res[0].iter().map(|r| r.uuid).collect::<Vec<_>>(),
+            res[2].iter().map(|r| r.uuid).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    pub fn test_query_include() {
+        let db = DuckDB::new(Default::default()).unwrap();
+        db.init().unwrap();
+
+        let collection = db
+            .create_collection("collection1", serde_json::json!({}))
+            .unwrap();
+
+        let e_model = EmbeddingModel {
+            embedding: vec![1.0, 2.0, 3.0],
+            uuid: Uuid::new_v4(),
+            metadata: serde_json::json!({ "source": "notion" }),
+            text: "hello world!".to_string(),
+        };
+        db.add_embeddings(collection.uuid, vec![e_model.clone()])
+            .unwrap();
+
+        let query = |include: Include| {
+            db.query(
+                collection.uuid,
+                &[vec![1.0, 2.0, 3.0].into()],
+                &Filter::All,
+                1,
+                &QueryOptions {
+                    include,
+                    ..Default::default()
+                },
+            )
+            .unwrap()
+            .remove(0)
+            .remove(0)
+        };
+
+        let res = query(Include::default());
+        assert_eq!(res.uuid, e_model.uuid);
+        assert!(res.distance.is_some());
+        assert_eq!(res.text, Some(e_model.text.clone()));
+        assert_eq!(res.metadata, Some(e_model.metadata.clone()));
+        assert_eq!(res.embedding, None);
+
+        let res = query(Include::all());
+        assert_eq!(res.embedding, Some(e_model.embedding.clone()));
+
+        let res = query(Include {
+            embeddings: true,
+            ..Include::none()
+        });
+        assert_eq!(res.uuid, e_model.uuid);
+        assert_eq!(res.distance, None);
+        assert_eq!(res.text, None);
+        assert_eq!(res.metadata, None);
+        assert_eq!(res.embedding, Some(e_model.embedding));
+    }
+
+    #[test]
+    pub fn test_delete_embeddings() {
+        let db = DuckDB::new(Default::default()).unwrap();
+        db.init().unwrap();
+
+        let collection = db
+            .create_collection("collection1", serde_json::json!({}))
+            .unwrap();
+
+        let models: Vec<_> = (0..4)
+            .map(|i| {
+                let mut embedding = vec![0.0; 384];
+                embedding[0] = 1.0;
+                embedding[1] = i as f32;
+                EmbeddingModel {
+                    embedding,
+                    uuid: Uuid::new_v4(),
+                    metadata: serde_json::json!({ "source": if i % 2 == 0 { "even" } else { "odd" } }),
+                    text: format!("document {i}"),
+                }
+            })
+            .collect();
+        db.add_embeddings(collection.uuid, models.clone()).unwrap();
+
+        assert_eq!(
+            1,
+            db.delete_embeddings(collection.uuid, &[models[0].uuid])
+                .unwrap()
+        );
+        assert_eq!(
+            2,
+            db.delete_embeddings_where(collection.uuid, &Filter::eq("source", "odd"))
+                .unwrap()
+        );
+        assert_eq!(1, db.count_embeddings(collection.uuid).unwrap());
+
+        let res = db
+            .query(
+                collection.uuid,
+                &[vec![1.0; 384].into()],
+                &Filter::All,
+                4,
+                &QueryOptions::default(),
+            )
+            .unwrap();
+
+        assert_eq!(res[0].len(), 1);
+        assert_eq!(res[0][0].uuid, models[2].uuid);
+    }
+
+    #[test]
+    pub fn test_compact_index_after_deletes() {
+        let db = DuckDB::new(Default::default()).unwrap();
+        db.init().unwrap();
+
+        let collection = db
+            .create_collection("collection1", serde_json::json!({}))
+            .unwrap();
+
+        let models: Vec<_> = (0..8)
+            .map(|i| {
+                let mut embedding = vec![0.0; 384];
+                embedding[0] = 1.0;
+                embedding[1] = i as f32;
+                EmbeddingModel {
+                    uuid: Uuid::new_v4(),
+                    ..embedding_model(embedding)
+                }
+            })
+            .collect();
+        db.add_embeddings(collection.uuid, models.clone()).unwrap();
+
+        let tombstones = || {
+            db.loaded_index(collection.uuid)
+                .unwrap()
+                .read()
+                .unwrap()
+                .tombstones()
+        };
+
+        db.delete_embeddings(collection.uuid, &[models[0].uuid])
+            .unwrap();
+        assert_eq!(tombstones(), 1);
+
+        // 3 of 8 points removed passes the threshold
+        db.delete_embeddings(collection.uuid, &[models[1].uuid, models[2].uuid])
+            .unwrap();
+        assert_eq!(tombstones(), 0);
+
+        let res = db
+            .query(
+                collection.uuid,
+                &[models[3].embedding.clone().into()],
+                &Filter::All,
+                8,
+                &QueryOptions::default(),
+            )
+            .unwrap();
+        assert_eq!(res[0].len(), 5);
+        assert_eq!(res[0][0].uuid, models[3].uuid);
+    }
+
+    #[test]
+    pub fn test_upsert_embeddings() {
+        let db = DuckDB::new(Default::default()).unwrap();
+        db.init().unwrap();
+
+        let collection = db
+            .create_collection("collection1", serde_json::json!({}))
+            .unwrap();
+
+        let mut e_model = embedding_model(vec![1.0; 384]);
+        db.add_embeddings(collection.uuid, vec![e_model.clone()])
+            .unwrap();
+
+        e_model.text = "hello, this is another sentence".to_string();
+        e_model.embedding = vec![0.5; 384];
+        db.upsert_embeddings(collection.uuid, vec![e_model.clone()])
+            .unwrap();
+
+        assert_eq!(
+            db.get_embeddings(collection.uuid).unwrap(),
+            vec![e_model.clone()]
+        );
+
+        let res = db
+            .query(
+                collection.uuid,
+                &[vec![1.0; 384].into()],
+                &Filter::All,
+                2,
+                &QueryOptions::default(),
+            )
+            .unwrap();
+        assert_eq!(res[0].len(), 1);
+        assert_eq!(res[0][0].text, Some(e_model.text.clone()));
+    }
+
+    #[test]
+    pub fn test_update_metadata() {
+        let db = DuckDB::new(Default::default()).unwrap();
+        db.init().unwrap();
+
+        let collection = db
+            .create_collection("collection1", serde_json::json!({}))
+            .unwrap();
+
+        let e_model = embedding_model(vec![1.0; 384]);
+        db.add_embeddings(collection.uuid, vec![e_model.clone()])
+            .unwrap();
+
+        db.update_metadata(
+            collection.uuid,
+            e_model.uuid,
+            serde_json::json!({"id": "103"}),
+        )
+        .unwrap();
+
+        let stored = db
+            .get_embeddings_by_ids(collection.uuid, &[e_model.uuid])
+            .unwrap();
+        assert_eq!(stored[0].metadata, serde_json::json!({"id": "103"}));
+        assert_eq!(stored[0].embedding, e_model.embedding);
+
+        assert!(db
+            .update_metadata(collection.uuid, Uuid::new_v4(), serde_json::json!({}))
+            .is_err());
+
+        for invalid in [
+            serde_json::json!(1),
+            serde_json::json!({"index:type": "flat"}),
+        ] {
+            assert!(matches!(
+                db.update_metadata(collection.uuid, e_model.uuid, invalid),
+                Err(DbError::InvalidValueError(_))
+            ));
+        }
+        let stored = db
+            .get_embeddings_by_ids(collection.uuid, &[e_model.uuid])
+            .unwrap();
+        assert_eq!(stored[0].metadata, serde_json::json!({"id": "103"}));
+    }
+
+    #[test]
+    pub fn test_get_embeddings_page() {
+        let db = DuckDB::new(Default::default()).unwrap();
+        db.init().unwrap();
+
+        let collection = db
+            .create_collection("collection1", serde_json::json!({}))
+            .unwrap();
+
+        let models: Vec<_> = (0..10)
+            .map(|i| EmbeddingModel {
+                embedding: vec![i as f32; 4],
+                uuid: Uuid::new_v4(),
+                metadata: serde_json::json!({ "even": i % 2 == 0 }),
+                text: format!("document {i}"),
+            })
+            .collect();
+        db.add_embeddings(collection.uuid, models.clone()).unwrap();
+
+        let page = db
+            .get_embeddings_page(collection.uuid, &Filter::All, 3, 4)
+            .unwrap();
+        assert_eq!(page, models[3..7].to_vec());
+
+        let page = db
+            .get_embeddings_page(collection.uuid, &Filter::eq("even", true), 1, 10)
+            .unwrap();
+        let expected: Vec<_> = models.iter().step_by(2).skip(1).cloned().collect();
+        assert_eq!(page, expected);
+    }
+
+    #[test]
+    pub fn test_collection_metric() {
+        let path = temp_db_path();
+
+        let e_model = embedding_model(vec![3.0, 4.0]);
+
+        {
+            let db = DuckDB::open(&path, Default::default()).unwrap();
+            db.init().unwrap();
+
+            let collection = db
+                .create_collection("collection1", serde_json::json!({ "hnsw:space": "l2" }))
+                .unwrap();
+            db.add_embeddings(collection.uuid, vec![e_model.clone()])
+                .unwrap();
+            db.persist().unwrap();
+        }
+
+        let db = DuckDB::open(&path, Default::default()).unwrap();
+        db.init().unwrap();
+
+        let collection = db.get_collection("collection1").unwrap().unwrap();
+        assert_eq!(
+            IndexConfig::from_metadata(&collection.metadata)
+                .unwrap()
+                .metric,
+            Metric::L2
+        );
+
+        let res = db
+            .query(
+                collection.uuid,
+                &[vec![0.0, 0.0].into()],
+                &Filter::All,
+                1,
+                &QueryOptions::default(),
+            )
+            .unwrap();
+        assert_eq!(res[0][0].distance, Some(5.0));
+
+        assert!(db
+            .create_collection(
+                "collection2",
+                serde_json::json!({ "hnsw:space": "hamming" })
+            )
+            .is_err());
+
+        drop(db);
+        remove_db_files(&path);
+    }
+
+    #[test]
+    pub fn test_collection_hnsw_config() {
+        let db = DuckDB::new(Default::default()).unwrap();
+        db.init().unwrap();
+
+        let collection = db
+            .create_collection(
+                "collection1",
+                serde_json::json!({ "hnsw:M": 8, "hnsw:search_ef": 64 }),
+            )
+            .unwrap();
+
+        let config = IndexConfig::from_metadata(&collection.metadata).unwrap();
+        assert_eq!(config.max_connections, 8);
+        assert_eq!(config.ef_search, 64);
+        assert_eq!(
+            config.ef_construction,
+            IndexConfig::default().ef_construction
+        );
+
+        assert!(db
+            .create_collection("collection2", serde_json::json!({ "hnsw:max_layers": 32 }))
+            .is_err());
+    }
+
+    #[test]
+    pub fn test_flat_index_collection() {
+        let path = temp_db_path();
+
+        let models: Vec<_> = (0..2000)
+            .map(|i| {
+                let x = i as f32 / 2000.0;
+                EmbeddingModel {
+                    embedding: vec![1.0 - x, x, 0.5],
+                    uuid: Uuid::new_v4(),
+                    metadata: serde_json::json!({ "even": i % 2 == 0 }),
+                    text: format!("document {i}"),
+                }
+            })
+            .collect();
+
+        {
+            let db = DuckDB::open(&path, Default::default()).unwrap();
+            db.init().unwrap();
+
+            let collection = db
+                .create_collection("collection1", serde_json::json!({ "index:type": "flat" }))
+                .unwrap();
+            db.add_embeddings(collection.uuid, models.clone()).unwrap();
+            db.persist().unwrap();
+        }
+
+        let db = DuckDB::open(&path, Default::default()).unwrap();
+        db.init().unwrap();
+
+        let collection = db.get_collection("collection1").unwrap().unwrap();
+        assert_eq!(
+            IndexConfig::from_metadata(&collection.metadata)
+                .unwrap()
+                .kind,
+            IndexKind::Flat
+        );
+
+        let query = vec![0.25, 0.75, 0.5];
+        let res = db
+            .query(
+                collection.uuid,
+                &[query.clone().into()],
+                &Filter::eq("even", false),
+                3,
+                &QueryOptions::default(),
+            )
+            .unwrap();
+
+        let mut expected: Vec<_> = models
+            .iter()
+            .filter(|m| m.metadata == serde_json::json!({ "even": false }))
+            .map(|m| (m.uuid, Metric::Cosine.distance(&query, &m.embedding)))
+            .collect();
+        expected.sort_by(|a, b| a.1.total_cmp(&b.1));
+
+        assert_eq!(res[0].len(), 3);
+        for (r, (uuid, distance)) in res[0].iter().zip(&expected) {
+            let r_distance = r.distance.unwrap();
+            assert!((r_distance - distance).abs() < 1e-5);
+            assert_eq!(r.metadata, Some(serde_json::json!({ "even": false })));
+            if (r_distance - distance).abs() > 1e-6 {
+                assert_eq!(r.uuid, *uuid);
+            }
+        }
+
+        assert!(db
+            .create_collection("collection2", serde_json::json!({ "index:type": "ivf" }))
+            .is_err());
+
+        drop(db);
+        remove_db_files(&path);
+    }
+
+    #[test]
+    pub fn test_ivf_index_collection() {
+        let db = DuckDB::new(Default::default()).unwrap();
+        db.init().unwrap();
+
+        let collection = db
+            .create_collection(
+                "collection1",
+                serde_json::json!({ "index:type": "ivf", "ivf:nlist": 8, "ivf:nprobe": 2 }),
+            )
+            .unwrap();
+
+        let models: Vec<_> = (0..2000)
+            .map(|i| {
+                let x = i as f32 / 2000.0;
+                EmbeddingModel {
+                    embedding: vec![x, 1.0 - x, (i % 7) as f32 / 7.0],
+                    uuid: Uuid::new_v4(),
+                    metadata: serde_json::json!({}),
+                    text: format!("document {i}"),
+                }
+            })
+            .collect();
+        db.add_embeddings(collection.uuid, models.clone()).unwrap();
+
+        let query = vec![0.3, 0.7, 0.5];
+        let mut expected: Vec<_> = models
+            .iter()
+            .map(|m| Metric::Cosine.distance(&query, &m.embedding))
+            .collect();
+        expected.sort_by(|a, b| a.total_cmp(b));
+
+        // probing every partition gives exact results
+        let options = QueryOptions {
+            nprobe: Some(8),
+            ..Default::default()
+        };
+        let res = db
+            .query(collection.uuid, &[query.into()], &Filter::All, 5, &options)
+            .unwrap();
+
+        assert_eq!(res[0].len(), 5);
+        for (r, distance) in res[0].iter().zip(&expected) {
+            assert!((r.distance.unwrap() - distance).abs() < 1e-5);
+        }
+
+        assert!(db
+            .create_collection(
+                "collection2",
+                serde_json::json!({ "index:type": "ivf", "ivf:nlist": 0 })
+            )
+            .is_err());
+    }
+
+    #[test]
+    pub fn test_quantized_collection_rescores() {
+        let db = DuckDB::new(Default::default()).unwrap();
+        db.init().unwrap();
+
+        let collection = db
+            .create_collection(
+                "collection1",
+                serde_json::json!({ "index:type": "flat", "index:quantization": "sq8" }),
+            )
+            .unwrap();
+
+        let models: Vec<_> = (0..2000)
+            .map(|i| {
+                let x = i as f32 / 2000.0;
+                EmbeddingModel {
+                    embedding: vec![x, 1.0 - x, (i % 13) as f32 / 13.0, 0.25],
+                    uuid: Uuid::new_v4(),
+                    metadata: serde_json::json!({}),
+                    text: format!("document {i}"),
+                }
+            })
+            .collect();
+        db.add_embeddings(collection.uuid, models.clone()).unwrap();
+
+        let query = models[1234].embedding.clone();
+        let res = db
+            .query(
+                collection.uuid,
+                &[query.clone().into()],
+                &Filter::All,
+                5,
+                &QueryOptions {
+                    include: Include::all(),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+
+        assert_eq!(res[0].len(), 5);
+        assert_eq!(res[0][0].uuid, models[1234].uuid);
+
+        // distances come from the full precision embeddings
+        for r in &res[0] {
+            assert_eq!(
+                r.distance.unwrap(),
+                Metric::Cosine.distance(&query, r.embedding.as_ref().unwrap())
+            );
+        }
+
+        assert!(db
+            .create_collection(
+                "collection2",
+                serde_json::json!({ "index:type": "hnsw", "index:quantization": "pq" })
+            )
+            .is_err());
+    }
+
+    #[test]
+    pub fn test_binary_quantized_collection() {
+        let db = DuckDB::new(Default::default()).unwrap();
+        db.init().unwrap();
+
+        let collection = db
+            .create_collection(
+                "collection1",
+                serde_json::json!({
+                    "index:type": "flat",
+                    "index:quantization": "binary",
+                    "index:rescore": 10,
+                }),
+            )
+            .unwrap();
+
+        let models: Vec<_> = random_vectors(2000, 64, 1)
+            .into_iter()
+            .enumerate()
+            .map(|(i, embedding)| EmbeddingModel {
+                embedding,
+                uuid: Uuid::new_v4(),
+                metadata: serde_json::json!({}),
+                text: format!("document {i}"),
+            })
+            .collect();
+        db.add_embeddings(collection.uuid, models.clone()).unwrap();
+
+        let query = models[1500].embedding.clone();
+        let res = db
+            .query(
+                collection.uuid,
+                &[query.clone().into()],
+                &Filter::All,
+                3,
+                &QueryOptions {
+                    include: Include::all(),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+
+        assert_eq!(res[0].len(), 3);
+        assert_eq!(res[0][0].uuid, models[1500].uuid);
+        assert!(res[0].windows(2).all(|w| w[0].distance <= w[1].distance));
+        for r in &res[0] {
+            assert_eq!(
+                r.distance.unwrap(),
+                Metric::Cosine.distance(&query, r.embedding.as_ref().unwrap())
+            );
+        }
+    }
+
+    #[test]
+    pub fn test_mmap_collection() {
+        let path = temp_db_path();
+        let metadata = serde_json::json!({ "index:type": "flat", "index:storage": "mmap" });
+
+        let models: Vec<_> = (0..1500)
+            .map(|i| {
+                let x = i as f32 / 1500.0;
+                EmbeddingModel {
+                    embedding: vec![x, 1.0 - x, (i % 3) as f32],
+                    uuid: Uuid::new_v4(),
+                    metadata: serde_json::json!({}),
+                    text: format!("document {i}"),
+                }
+            })
+            .collect();
+
+        let collection_uuid = {
+            let db = DuckDB::open(&path, Default::default()).unwrap();
+            db.init().unwrap();
+
+            let collection = db
+                .create_collection("collection1", metadata.clone())
+                .unwrap();
+            db.add_embeddings(collection.uuid, models.clone()).unwrap();
+            db.persist().unwrap();
+
+            collection.uuid
+        };
+
+        let basename = format!("{}.index/{}", path.display(), collection_uuid.simple());
+        assert!(Path::new(&format!("{basename}.flat.vectors")).exists());
+        assert!(!Path::new(&format!("{basename}.flat.data")).exists());
+
+        let db = DuckDB::open(&path, Default::default()).unwrap();
+        db.init().unwrap();
+
+        let query = vec![0.3, 0.7, 1.0];
+        let res = db
+            .query(
+                collection_uuid,
+                &[query.clone().into()],
+                &Filter::All,
+                5,
+                &QueryOptions::default(),
+            )
+            .unwrap();
+
+        let mut expected: Vec<_> = models
+            .iter()
+            .map(|m| Metric::Cosine.distance(&query, &m.embedding))
+            .collect();
+        expected.sort_by(|a, b| a.total_cmp(b));
+
+        assert_eq!(res[0].len(), 5);
+        for (r, distance) in res[0].iter().zip(&expected) {
+            assert!((r.distance.unwrap() - distance).abs() < 1e-5);
+        }
+
+        db.delete_collection(collection_uuid).unwrap();
+        assert!(!Path::new(&format!("{basename}.flat.vectors")).exists());
+
+        drop(db);
+        remove_db_files(&path);
+
+        // the vectors need a file to live in
+        let db = DuckDB::new(Default::default()).unwrap();
+        db.init().unwrap();
+        assert!(db.create_collection("collection1", metadata).is_err());
+    }
+
+    #[test]
+    pub fn test_concurrent_queries_and_writes() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<DuckDB>();
+
+        let db = DuckDB::new(Default::default()).unwrap();
+        db.init().unwrap();
+
+        let collection = db
+            .create_collection("collection1", serde_json::json!({ "index:type": "flat" }))
+            .unwrap();
+
+        let model = |i: usize| EmbeddingModel {
+            embedding: vec![1.0, i as f32],
+            uuid: Uuid::new_v4(),
+            metadata: serde_json::json!({ "i": i }),
+            text: format!("document {i}"),
+        };
+        db.add_embeddings(collection.uuid, (0..100).map(model).collect())
+            .unwrap();
+
+        std::thread::scope(|scope| {
+            let writer = scope.spawn(|| {
+                for batch in 1..=10 {
+                    let models = (batch * 100..(batch + 1) * 100).map(model).collect();
+                    db.add_embeddings(collection.uuid, models).unwrap();
+                }
+            });
+
+            let readers: Vec<_> = (0..4)
+                .map(|_| {
+                    scope.spawn(|| {
+                        for _ in 0..20 {
+                            let res = db
+                                .query(
+                                    collection.uuid,
+                                    &[vec![1.0, 0.0].into()],
+                                    &Filter::All,
+                                    5,
+                                    &QueryOptions::default(),
+                                )
+                                .unwrap();
+                            assert_eq!(res[0].len(), 5);
+                        }
+                    })
+                })
+                .collect();
+
+            writer.join().unwrap();
+            for reader in readers {
+                reader.join().unwrap();
+            }
+        });
+
+        assert_eq!(db.count_embeddings(collection.uuid).unwrap(), 1100);
+    }
+
+    #[test]
+    pub fn test_reset() {
+        let db = DuckDB::new(Default::default()).unwrap();
+        db.init().unwrap();
+
+        let collection = db
+            .create_collection("collection1", serde_json::json!({}))
+            .unwrap();
+        let e_model = embedding_model(vec![0.0; 384]);
+        db.add_embeddings(collection.uuid, vec![e_model]).unwrap();
+
+        db.reset().unwrap();
+
+        assert!(db.list_collections().unwrap().is_empty());
+        assert_eq!(0, db.count_embeddings(collection.uuid).unwrap());
+        assert!(db.index.read().unwrap().is_empty());
+    }
+
     #[test]
     pub fn test_query_fmt() {
         use crate::client::Client;
@@ -695,5 +3010,18 @@ mod tests {
                 1,
             )
             .unwrap();
+        assert_eq!(res[0].len(), 1);
+        assert_eq!(res[0][0].uuid, docs[1].id);
+
+        let res = collection
+            .query_documents(
+                &["which one is the better fruit?"],
+                serde_json::json!({}),
+                2,
+            )
+            .unwrap();
+        let uuids: Vec<_> = res[0].iter().map(|r| r.uuid).collect();
+        assert_eq!(uuids, vec![docs[1].id, docs[0].id]);
+        assert!(res[0][0].distance.unwrap() <= res[0][1].distance.unwrap());
     }
 }
@@ -1,4 +1,5 @@
 pub mod duckdb;
+mod pool;
 
 pub use self::duckdb::DuckDB;
 
@@ -7,18 +8,28 @@ pub(crate) mod model;
 pub use model::CollectionModel;
 use uuid::Uuid;
 
-use crate::{Embedding, QueryResult};
+use crate::{filter::Filter, index::IndexError, Embedding, QueryOptions, QueryResult};
 
 use self::model::EmbeddingModel;
 
-pub trait Db {
+/// Storage backend of a client. Implementations are shared between threads.
+pub trait Db: Send + Sync {
     fn init(&self) -> Result<(), DbError>;
     fn reset(&self) -> Result<(), DbError>;
     fn persist(&self) -> Result<(), DbError>;
 
     fn get_collection(&self, name: &str) -> Result<Option<CollectionModel>, DbError>;
-    fn create_collection(&self, name: &str) -> Result<CollectionModel, DbError>;
-    fn get_or_create_collection(&self, name: &str) -> Result<CollectionModel, DbError>;
+    fn get_collection_by_uuid(&self, uuid: Uuid) -> Result<Option<CollectionModel>, DbError>;
+    fn create_collection(
+        &self,
+        name: &str,
+        metadata: serde_json::Value,
+    ) -> Result<CollectionModel, DbError>;
+    fn get_or_create_collection(
+        &self,
+        name: &str,
+        metadata: serde_json::Value,
+    ) -> Result<CollectionModel, DbError>;
     fn list_collections(&self) -> Result<Vec<CollectionModel>, DbError>;
     fn get_collection_uuid_from_name(&self, name: &str) -> Result<Option<uuid::Uuid>, DbError>;
     fn update_collection(
@@ -26,38 +37,73 @@ pub trait Db {
         uuid: uuid::Uuid,
         new_name: &str,
     ) -> Result<CollectionModel, DbError>;
+    fn delete_collection(&self, uuid: uuid::Uuid) -> Result<(), DbError>;
+    /// Records `model` as the embedding model of the collection unless one is recorded
+    /// already. Returns the recorded model.
+    fn set_embedding_model(&self, uuid: Uuid, model: &str) -> Result<String, DbError>;
+    /// Replaces the user metadata of the collection. Reserved keys are kept.
+    fn update_collection_metadata(
+        &self,
+        uuid: Uuid,
+        metadata: serde_json::Value,
+    ) -> Result<CollectionModel, DbError>;
 
     fn add_embeddings(
         &self,
         collection_uuid: Uuid,
         embeddings: Vec<EmbeddingModel>,
     ) -> Result<(), DbError>;
+    fn upsert_embeddings(
+        &self,
+        collection_uuid: Uuid,
+        embeddings: Vec<EmbeddingModel>,
+    ) -> Result<(), DbError>;
+    fn update_metadata(
+        &self,
+        collection_uuid: Uuid,
+        uuid: Uuid,
+        metadata: serde_json::Value,
+    ) -> Result<(), DbError>;
     fn count_embeddings(&self, collection_uuid: Uuid) -> Result<usize, DbError>;
 
+    fn delete_embeddings(&self, collection_uuid: Uuid, uuids: &[Uuid]) -> Result<usize, DbError>;
+    fn delete_embeddings_where(
+        &self,
+        collection_uuid: Uuid,
+        filter: &Filter,
+    ) -> Result<usize, DbError>;
+
     fn get_embeddings(&self, collection_uuid: Uuid) -> Result<Vec<EmbeddingModel>, DbError>;
+    fn get_embeddings_by_ids(
+        &self,
+        collection_uuid: Uuid,
+        uuids: &[Uuid],
+    ) -> Result<Vec<EmbeddingModel>, DbError>;
+    fn get_embeddings_page(
+        &self,
+        collection_uuid: Uuid,
+        filter: &Filter,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<EmbeddingModel>, DbError>;
 
     fn query(
         &self,
         collection_uuid: Uuid,
         embeddings: &[Embedding],
-        _where: serde_json::Value,
+        filter: &Filter,
         k: usize,
+        options: &QueryOptions,
     ) -> Result<Vec<Vec<QueryResult>>, DbError>;
-
-    fn format_where(
-        &self,
-        where_map: &serde_json::Map<String, serde_json::Value>,
-        result: &mut Vec<String>,
-    ) -> Result<(), DbError>;
 }
 
 #[derive(thiserror::Error, Debug)]
 pub enum DbError {
     #[error("Failed to initialize DB: {0}")]
-    DbInitError(Box<dyn std::error::Error>),
+    DbInitError(Box<dyn std::error::Error + Send + Sync>),
 
     #[error("Failed to execute SQL query: {0}")]
-    SqlError(Box<dyn std::error::Error>),
+    SqlError(Box<dyn std::error::Error + Send + Sync>),
 
     #[error("{0}")]
     UpdateError(String),
@@ -70,4 +116,16 @@ pub enum DbError {
 
     #[error("{0}")]
     InvalidValueError(String),
+
+    #[error("Collection {0} does not exist")]
+    CollectionNotFoundError(Uuid),
+
+    #[error("Embedding {0} does not exist")]
+    EmbeddingNotFoundError(Uuid),
+
+    #[error("Collection holds embeddings of dimension {expected}, found {found}")]
+    DimensionError { expected: usize, found: usize },
+
+    #[error("Index operation failed: {0}")]
+    IndexError(#[from] IndexError),
 }
@@ -1,16 +1,86 @@
+use serde_json::{Map, Value};
 use uuid::Uuid;
 
+use crate::Document;
+
+use super::DbError;
+
+pub(crate) const DIMENSION_KEY: &str = "embedding:dimension";
+pub(crate) const MODEL_KEY: &str = "embedding:model";
+
+// Metadata keys with these prefixes hold the index configuration and the recorded embedding
+// dimension and model. They are managed by cedar and cannot be set by users.
+const RESERVED_PREFIXES: [&str; 5] = ["index:", "hnsw:", "ivf:", "pq:", "embedding:"];
+
+pub(crate) fn is_reserved_key(key: &str) -> bool {
+    RESERVED_PREFIXES
+        .iter()
+        .any(|prefix| key.starts_with(prefix))
+}
+
+/// Checks that collection or document `metadata` set by a user is an object without reserved
+/// keys. `null` is accepted as an empty object.
+pub(crate) fn validate_user_metadata(metadata: Value) -> Result<Map<String, Value>, DbError> {
+    let map = match metadata {
+        Value::Object(map) => map,
+        Value::Null => Map::new(),
+        _ => {
+            return Err(DbError::InvalidValueError(format!(
+                "metadata must be an object, found {metadata}"
+            )))
+        }
+    };
+
+    match map.keys().find(|key| is_reserved_key(key)) {
+        Some(key) => Err(DbError::InvalidValueError(format!(
+            "metadata key {key} is reserved"
+        ))),
+        None => Ok(map),
+    }
+}
+
 #[derive(Debug, PartialEq, Eq)]
 pub struct CollectionModel {
     pub(crate) name: String,
     pub(crate) uuid: Uuid,
-    pub(crate) metadata: serde_json::Value,
+    pub(crate) metadata: Value,
+}
+
+impl CollectionModel {
+    /// Dimension of the embeddings of the collection, recorded on the first insert.
+    pub(crate) fn dimension(&self) -> Option<usize> {
+        self.metadata[DIMENSION_KEY].as_u64().map(|d| d as usize)
+    }
+
+    /// Model that produced the embeddings of the collection, recorded on the first insert.
+    pub(crate) fn embedding_model(&self) -> Option<&str> {
+        self.metadata[MODEL_KEY].as_str()
+    }
+
+    /// Metadata set by the user, without the reserved keys.
+    pub(crate) fn user_metadata(&self) -> Value {
+        match &self.metadata {
+            Value::Object(map) => Value::Object(
+                map.iter()
+                    .filter(|(key, _)| !is_reserved_key(key))
+                    .map(|(key, value)| (key.clone(), value.clone()))
+                    .collect(),
+            ),
+            _ => Value::Object(Map::new()),
+        }
+    }
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct EmbeddingModel {
     pub(crate) embedding: Vec<f32>,
     pub(crate) uuid: Uuid,
-    pub(crate) metadata: serde_json::Value,
+    pub(crate) metadata: Value,
     pub(crate) text: String,
 }
+
+impl From<EmbeddingModel> for Document {
+    fn from(value: EmbeddingModel) -> Self {
+        Document::new_with_id(value.text, value.metadata, value.uuid)
+    }
+}
@@ -0,0 +1,76 @@
+use std::{
+    ops::{Deref, DerefMut},
+    sync::Mutex,
+};
+
+use duckdb::Connection;
+
+/// Connections to a single database that can be shared between threads.
+///
+/// A DuckDB connection can only be used by one thread at a time, so every operation checks out
+/// its own connection and returns it to the pool when done. Connections are opened on demand
+/// by cloning the first one, so the pool grows to the number of concurrent operations.
+#[derive(Debug)]
+pub(crate) struct ConnectionPool {
+    root: Mutex<Connection>,
+    idle: Mutex<Vec<Connection>>,
+}
+
+impl ConnectionPool {
+    pub(crate) fn new(conn: Connection) -> Self {
+        Self {
+            root: Mutex::new(conn),
+            idle: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub(crate) fn get(&self) -> Result<PooledConnection<'_>, duckdb::Error> {
+        let idle = self.idle.lock().expect("connection pool poisoned").pop();
+
+        let conn = match idle {
+            Some(conn) => conn,
+            None => self
+                .root
+                .lock()
+                .expect("connection pool poisoned")
+                .try_clone()?,
+        };
+
+        Ok(PooledConnection {
+            pool: self,
+            conn: Some(conn),
+        })
+    }
+}
+
+/// A connection checked out of a [`ConnectionPool`]. It goes back to the pool when dropped.
+pub(crate) struct PooledConnection<'a> {
+    pool: &'a ConnectionPool,
+    conn: Option<Connection>,
+}
+
+impl Deref for PooledConnection<'_> {
+    type Target = Connection;
+
+    fn deref(&self) -> &Connection {
+        self.conn
+            .as_ref()
+            .expect("connection was returned to the pool")
+    }
+}
+
+impl DerefMut for PooledConnection<'_> {
+    fn deref_mut(&mut self) -> &mut Connection {
+        self.conn
+            .as_mut()
+            .expect("connection was returned to the pool")
+    }
+}
+
+impl Drop for PooledConnection<'_> {
+    fn drop(&mut self) {
+        if let (Some(conn), Ok(mut idle)) = (self.conn.take(), self.pool.idle.lock()) {
+            idle.push(conn);
+        }
+    }
+}
@@ -1,3 +1,7 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+
 use crate::Embedding;
 
 pub(crate) mod openai;
@@ -6,15 +10,63 @@ pub(crate) mod sentencetransformer;
 pub use openai::OpenAIEmbeddingFunction;
 pub use sentencetransformer::SentenceTransformerEmbeddings;
 
-pub trait EmbeddingFunction {
+pub trait EmbeddingFunction: Send + Sync {
     fn embed<I, S: AsRef<str> + Sync>(&self, texts: I) -> Result<Vec<Embedding>, EmbeddingError>
     where
         I: IntoIterator<Item = S>;
+
+    /// Identifier of the model producing the embeddings. Collections record the model of the
+    /// first documents added to them and reject other models. `None` skips the check.
+    fn model(&self) -> Option<&str> {
+        None
+    }
+}
+
+/// Embedding function for async code. Implementations must not block the calling task.
+#[async_trait]
+pub trait AsyncEmbeddingFunction: Send + Sync {
+    async fn embed(&self, texts: &[&str]) -> Result<Vec<Embedding>, EmbeddingError>;
+
+    /// See [`EmbeddingFunction::model`].
+    fn model(&self) -> Option<&str> {
+        None
+    }
+}
+
+/// Runs a blocking [`EmbeddingFunction`], e.g. a local model, on tokio's blocking thread pool
+/// so that it can be used where an [`AsyncEmbeddingFunction`] is expected.
+#[derive(Debug)]
+pub struct BlockingEmbeddings<E> {
+    embedding_fn: Arc<E>,
+}
+
+impl<E: EmbeddingFunction> BlockingEmbeddings<E> {
+    pub fn new(embedding_fn: E) -> Self {
+        Self {
+            embedding_fn: Arc::new(embedding_fn),
+        }
+    }
+}
+
+#[async_trait]
+impl<E: EmbeddingFunction + 'static> AsyncEmbeddingFunction for BlockingEmbeddings<E> {
+    async fn embed(&self, texts: &[&str]) -> Result<Vec<Embedding>, EmbeddingError> {
+        let embedding_fn = self.embedding_fn.clone();
+        let texts: Vec<String> = texts.iter().map(|text| text.to_string()).collect();
+
+        tokio::task::spawn_blocking(move || embedding_fn.embed(texts))
+            .await
+            .map_err(|e| EmbeddingError { err: e.into() })?
+    }
+
+    fn model(&self) -> Option<&str> {
+        self.embedding_fn.model()
+    }
 }
 
 #[derive(thiserror::Error, Debug)]
 #[error("failed to embed document")]
 pub struct EmbeddingError {
     #[source]
-    err: Box<dyn std::error::Error>,
+    err: Box<dyn std::error::Error + Send + Sync>,
 }
@@ -1,15 +1,19 @@
+use async_trait::async_trait;
 use serde::Deserialize;
-use serde_json::json;
+use serde_json::{json, Value};
 
 use crate::Embedding;
 
-use super::{EmbeddingError, EmbeddingFunction};
+use super::{AsyncEmbeddingFunction, EmbeddingError, EmbeddingFunction};
 
 const OPENAI_EMBEDDING_MODEL: &str = "text-embedding-ada-002";
 const OPENAI_EMBEDDING_ENDPOINT: &str = "https://api.openai.com/v1/embeddings";
 
+/// Embeds texts with the OpenAI API. It can be used both as a blocking [`EmbeddingFunction`]
+/// and as an [`AsyncEmbeddingFunction`].
 pub struct OpenAIEmbeddingFunction {
     client: reqwest::blocking::Client,
+    async_client: reqwest::Client,
 }
 
 impl OpenAIEmbeddingFunction {
@@ -25,36 +29,71 @@ impl OpenAIEmbeddingFunction {
         );
 
         let client = reqwest::blocking::ClientBuilder::new()
+            .default_headers(headers.clone())
+            .build()
+            .unwrap();
+        let async_client = reqwest::ClientBuilder::new()
             .default_headers(headers)
             .build()
             .unwrap();
-        Self { client }
+        Self {
+            client,
+            async_client,
+        }
     }
 }
 
+fn payload<S: AsRef<str>>(texts: impl IntoIterator<Item = S>) -> Value {
+    let texts: Vec<_> = texts.into_iter().map(|s| s.as_ref().to_string()).collect();
+
+    json!({
+        "model": OPENAI_EMBEDDING_MODEL,
+        "input": texts
+    })
+}
+
 impl EmbeddingFunction for OpenAIEmbeddingFunction {
     fn embed<I, S: AsRef<str> + Sync>(&self, texts: I) -> Result<Vec<Embedding>, EmbeddingError>
     where
         I: IntoIterator<Item = S>,
     {
-        let texts: Vec<_> = texts.into_iter().map(|s| s.as_ref().to_string()).collect();
+        let res: ApiResponse = self
+            .client
+            .post(OPENAI_EMBEDDING_ENDPOINT)
+            .json(&payload(texts))
+            .send()
+            .map_err(|e| EmbeddingError { err: e.into() })?
+            .json()
+            .map_err(|e| EmbeddingError { err: e.into() })?;
+
+        Ok(res.data.into_iter().map(|e| e.into()).collect())
+    }
 
-        let payload = json!({
-            "model": OPENAI_EMBEDDING_MODEL,
-            "input": texts
-        });
+    fn model(&self) -> Option<&str> {
+        Some(OPENAI_EMBEDDING_MODEL)
+    }
+}
 
+#[async_trait]
+impl AsyncEmbeddingFunction for OpenAIEmbeddingFunction {
+    async fn embed(&self, texts: &[&str]) -> Result<Vec<Embedding>, EmbeddingError> {
         let res: ApiResponse = self
-            .client
+            .async_client
             .post(OPENAI_EMBEDDING_ENDPOINT)
-            .json(&payload)
+            .json(&payload(texts))
             .send()
+            .await
             .map_err(|e| EmbeddingError { err: e.into() })?
             .json()
+            .await
             .map_err(|e| EmbeddingError { err: e.into() })?;
 
         Ok(res.data.into_iter().map(|e| e.into()).collect())
     }
+
+    fn model(&self) -> Option<&str> {
+        Some(OPENAI_EMBEDDING_MODEL)
+    }
 }
 
 #[derive(Debug, Deserialize)]
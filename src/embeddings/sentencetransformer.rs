@@ -1,4 +1,4 @@
-use std::fmt::Debug;
+use std::{fmt::Debug, sync::Mutex};
 
 use rust_bert::pipelines::sentence_embeddings::{
     SentenceEmbeddingsBuilder, SentenceEmbeddingsModel, SentenceEmbeddingsModelType::AllMiniLmL6V2,
@@ -8,8 +8,11 @@ use crate::Embedding;
 
 use super::{EmbeddingError, EmbeddingFunction};
 
+const SENTENCE_TRANSFORMER_MODEL: &str = "sentence-transformers/all-MiniLM-L6-v2";
+
 pub struct SentenceTransformerEmbeddings {
-    model: SentenceEmbeddingsModel,
+    // the model is not safe to run from several threads at once
+    model: Mutex<SentenceEmbeddingsModel>,
 }
 
 impl Debug for SentenceTransformerEmbeddings {
@@ -25,7 +28,9 @@ impl SentenceTransformerEmbeddings {
             .create_model()
             .expect("failed to load embedding model");
 
-        Self { model }
+        Self {
+            model: Mutex::new(model),
+        }
     }
 }
 
@@ -35,10 +40,16 @@ impl EmbeddingFunction for SentenceTransformerEmbeddings {
         I: IntoIterator<Item = S>,
     {
         self.model
+            .lock()
+            .expect("embedding model poisoned")
             .encode(&texts.into_iter().collect::<Vec<_>>())
             .map(|v| v.into_iter().map(Into::<Embedding>::into).collect())
             .map_err(|e| EmbeddingError { err: e.into() })
     }
+
+    fn model(&self) -> Option<&str> {
+        Some(SENTENCE_TRANSFORMER_MODEL)
+    }
 }
 
 #[cfg(test)]
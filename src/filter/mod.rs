@@ -0,0 +1,368 @@
+use std::ops::Not;
+
+use serde_json::{Map, Value};
+
+/// A predicate over the metadata (and text) of stored documents.
+///
+/// Filters are usually built with the constructors below, e.g.
+/// `Filter::eq("source", "facts").and(Filter::gt("year", 2020))`, or parsed from the JSON
+/// where-syntax with `Filter::try_from(json!({ "source": "facts" }))`.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum Filter {
+    #[default]
+    All,
+    Eq(String, FilterValue),
+    Ne(String, FilterValue),
+    Gt(String, f64),
+    Gte(String, f64),
+    Lt(String, f64),
+    Lte(String, f64),
+    In(String, Vec<FilterValue>),
+    Nin(String, Vec<FilterValue>),
+    Contains(String),
+    And(Vec<Filter>),
+    Or(Vec<Filter>),
+    Not(Box<Filter>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FilterValue {
+    String(String),
+    Number(f64),
+    Bool(bool),
+}
+
+impl From<&str> for FilterValue {
+    fn from(value: &str) -> Self {
+        FilterValue::String(value.to_string())
+    }
+}
+
+impl From<String> for FilterValue {
+    fn from(value: String) -> Self {
+        FilterValue::String(value)
+    }
+}
+
+impl From<f64> for FilterValue {
+    fn from(value: f64) -> Self {
+        FilterValue::Number(value)
+    }
+}
+
+impl From<i64> for FilterValue {
+    fn from(value: i64) -> Self {
+        FilterValue::Number(value as f64)
+    }
+}
+
+impl From<i32> for FilterValue {
+    fn from(value: i32) -> Self {
+        FilterValue::Number(value as f64)
+    }
+}
+
+impl From<bool> for FilterValue {
+    fn from(value: bool) -> Self {
+        FilterValue::Bool(value)
+    }
+}
+
+impl TryFrom<&Value> for FilterValue {
+    type Error = FilterError;
+
+    fn try_from(value: &Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::String(s) => Ok(FilterValue::String(s.clone())),
+            Value::Bool(b) => Ok(FilterValue::Bool(*b)),
+            Value::Number(n) => n
+                .as_f64()
+                .map(FilterValue::Number)
+                .ok_or_else(|| FilterError::OperandError(format!("invalid number {n}"))),
+            _ => Err(FilterError::OperandError(format!(
+                "expected a string, number or boolean, found {value}"
+            ))),
+        }
+    }
+}
+
+impl Filter {
+    pub fn eq(key: &str, value: impl Into<FilterValue>) -> Self {
+        Filter::Eq(key.to_string(), value.into())
+    }
+
+    pub fn ne(key: &str, value: impl Into<FilterValue>) -> Self {
+        Filter::Ne(key.to_string(), value.into())
+    }
+
+    pub fn gt(key: &str, value: impl Into<f64>) -> Self {
+        Filter::Gt(key.to_string(), value.into())
+    }
+
+    pub fn gte(key: &str, value: impl Into<f64>) -> Self {
+        Filter::Gte(key.to_string(), value.into())
+    }
+
+    pub fn lt(key: &str, value: impl Into<f64>) -> Self {
+        Filter::Lt(key.to_string(), value.into())
+    }
+
+    pub fn lte(key: &str, value: impl Into<f64>) -> Self {
+        Filter::Lte(key.to_string(), value.into())
+    }
+
+    pub fn is_in<V: Into<FilterValue>>(key: &str, values: impl IntoIterator<Item = V>) -> Self {
+        Filter::In(
+            key.to_string(),
+            values.into_iter().map(Into::into).collect(),
+        )
+    }
+
+    pub fn not_in<V: Into<FilterValue>>(key: &str, values: impl IntoIterator<Item = V>) -> Self {
+        Filter::Nin(
+            key.to_string(),
+            values.into_iter().map(Into::into).collect(),
+        )
+    }
+
+    pub fn contains(text: &str) -> Self {
+        Filter::Contains(text.to_string())
+    }
+
+    pub fn and(self, other: Filter) -> Self {
+        match (self, other) {
+            (Filter::All, f) | (f, Filter::All) => f,
+            (Filter::And(mut filters), Filter::And(others)) => {
+                filters.extend(others);
+                Filter::And(filters)
+            }
+            (Filter::And(mut filters), f) => {
+                filters.push(f);
+                Filter::And(filters)
+            }
+            (f, other) => Filter::And(vec![f, other]),
+        }
+    }
+
+    pub fn or(self, other: Filter) -> Self {
+        match (self, other) {
+            (Filter::All, _) | (_, Filter::All) => Filter::All,
+            (Filter::Or(mut filters), Filter::Or(others)) => {
+                filters.extend(others);
+                Filter::Or(filters)
+            }
+            (Filter::Or(mut filters), f) => {
+                filters.push(f);
+                Filter::Or(filters)
+            }
+            (f, other) => Filter::Or(vec![f, other]),
+        }
+    }
+
+    fn from_map(map: &Map<String, Value>) -> Result<Self, FilterError> {
+        let mut filters = vec![];
+
+        for (key, value) in map {
+            let filter = match key.as_str() {
+                "$and" | "$or" => {
+                    let subfilters = value
+                        .as_array()
+                        .ok_or_else(|| {
+                            FilterError::OperandError(format!(
+                                "{key} expects an array of filters, found {value}"
+                            ))
+                        })?
+                        .iter()
+                        .map(Filter::try_from)
+                        .collect::<Result<Vec<_>, _>>()?;
+
+                    if key == "$and" {
+                        Filter::And(subfilters)
+                    } else {
+                        Filter::Or(subfilters)
+                    }
+                }
+                "$not" => Filter::Not(Box::new(Filter::try_from(value)?)),
+                "$contains" => Filter::Contains(
+                    value
+                        .as_str()
+                        .ok_or_else(|| {
+                            FilterError::OperandError(format!(
+                                "$contains expects a string, found {value}"
+                            ))
+                        })?
+                        .to_string(),
+                ),
+                _ if key.starts_with('$') => {
+                    return Err(FilterError::OperatorError(format!(
+                        "Invalid operator: expected one of $and, $or, $not, $contains, found {key}"
+                    )))
+                }
+                _ => Filter::from_field(key, value)?,
+            };
+
+            filters.push(filter);
+        }
+
+        Ok(match filters.len() {
+            0 => Filter::All,
+            1 => filters.remove(0),
+            _ => Filter::And(filters),
+        })
+    }
+
+    fn from_field(key: &str, value: &Value) -> Result<Self, FilterError> {
+        let Value::Object(ops) = value else {
+            return Ok(Filter::Eq(key.to_string(), value.try_into()?));
+        };
+
+        // an empty `And` would match every document
+        if ops.is_empty() {
+            return Err(FilterError::OperatorError(format!(
+                "Expected an operator for {key}, found {{}}"
+            )));
+        }
+
+        let mut filters = vec![];
+
+        for (operator, operand) in ops {
+            let number = || {
+                operand.as_f64().ok_or_else(|| {
+                    FilterError::OperandError(format!("Operand {operand} not valid for {operator}"))
+                })
+            };
+            let list = || -> Result<Vec<FilterValue>, FilterError> {
+                operand
+                    .as_array()
+                    .ok_or_else(|| {
+                        FilterError::OperandError(format!(
+                            "Operand {operand} not valid for {operator}"
+                        ))
+                    })?
+                    .iter()
+                    .map(FilterValue::try_from)
+                    .collect()
+            };
+
+            let key = key.to_string();
+            filters.push(match operator.as_str() {
+                "$eq" => Filter::Eq(key, operand.try_into()?),
+                "$ne" => Filter::Ne(key, operand.try_into()?),
+                "$gt" => Filter::Gt(key, number()?),
+                "$gte" => Filter::Gte(key, number()?),
+                "$lt" => Filter::Lt(key, number()?),
+                "$lte" => Filter::Lte(key, number()?),
+                "$in" => Filter::In(key, list()?),
+                "$nin" => Filter::Nin(key, list()?),
+                _ => {
+                    return Err(FilterError::OperatorError(format!(
+                        "Invalid operator: expected one of $eq, $ne, $lt, $lte, $gt, $gte, $in, $nin, found {operator}"
+                    )))
+                }
+            });
+        }
+
+        Ok(match filters.len() {
+            1 => filters.remove(0),
+            _ => Filter::And(filters),
+        })
+    }
+}
+
+impl Not for Filter {
+    type Output = Filter;
+
+    fn not(self) -> Self::Output {
+        match self {
+            Filter::Not(f) => *f,
+            f => Filter::Not(Box::new(f)),
+        }
+    }
+}
+
+impl TryFrom<&Value> for Filter {
+    type Error = FilterError;
+
+    fn try_from(value: &Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::Object(map) => Filter::from_map(map),
+            Value::Null => Ok(Filter::All),
+            _ => Err(FilterError::InvalidFilterError(format!(
+                "invalid where clause: {value}"
+            ))),
+        }
+    }
+}
+
+impl TryFrom<Value> for Filter {
+    type Error = FilterError;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        Filter::try_from(&value)
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum FilterError {
+    #[error("{0}")]
+    InvalidFilterError(String),
+
+    #[error("{0}")]
+    OperandError(String),
+
+    #[error("{0}")]
+    OperatorError(String),
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::{Filter, FilterValue};
+
+    #[test]
+    pub fn test_parse_where() {
+        let filter = Filter::try_from(json!({
+            "$or": [
+                { "source": "facts" },
+                { "$and": [{ "year": { "$gte": 2020 } }, { "draft": false }] },
+            ]
+        }))
+        .unwrap();
+
+        assert_eq!(
+            filter,
+            Filter::eq("source", "facts")
+                .or(Filter::gte("year", 2020).and(Filter::eq("draft", false)))
+        );
+    }
+
+    #[test]
+    pub fn test_parse_in() {
+        let filter = Filter::try_from(json!({ "source": { "$in": ["facts", 1] } })).unwrap();
+
+        assert_eq!(
+            filter,
+            Filter::In(
+                "source".to_string(),
+                vec![FilterValue::from("facts"), FilterValue::Number(1.0)]
+            )
+        );
+    }
+
+    #[test]
+    pub fn test_parse_empty() {
+        assert_eq!(Filter::try_from(json!({})).unwrap(), Filter::All);
+    }
+
+    #[test]
+    pub fn test_parse_invalid_operator() {
+        assert!(Filter::try_from(json!({ "source": { "$like": "facts" } })).is_err());
+        assert!(Filter::try_from(json!({ "$xor": [] })).is_err());
+    }
+
+    #[test]
+    pub fn test_parse_empty_operators() {
+        assert!(Filter::try_from(json!({ "source": {} })).is_err());
+    }
+}
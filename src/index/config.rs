@@ -0,0 +1,422 @@
+use std::{fmt::Display, str::FromStr};
+
+use derive_builder::Builder;
+use hnsw_rs::prelude::*;
+use serde_json::{json, Map, Value};
+
+use super::IndexError;
+
+const KIND_KEY: &str = "index:type";
+const QUANTIZATION_KEY: &str = "index:quantization";
+const RESCORE_KEY: &str = "index:rescore";
+const STORAGE_KEY: &str = "index:storage";
+const SPACE_KEY: &str = "hnsw:space";
+const M_KEY: &str = "hnsw:M";
+const CONSTRUCTION_EF_KEY: &str = "hnsw:construction_ef";
+const MAX_LAYERS_KEY: &str = "hnsw:max_layers";
+const CAPACITY_KEY: &str = "hnsw:capacity";
+const SEARCH_EF_KEY: &str = "hnsw:search_ef";
+const NLIST_KEY: &str = "ivf:nlist";
+const NPROBE_KEY: &str = "ivf:nprobe";
+const PQ_SUBVECTORS_KEY: &str = "pq:subvectors";
+
+// hnsw_rs does not support more layers than this
+const MAX_LAYERS: usize = 16;
+
+/// Distance function used to compare embeddings in a collection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Metric {
+    /// `1 - cos(a, b)`
+    #[default]
+    Cosine,
+    /// Euclidean distance `|a - b|`
+    L2,
+    /// `1 - <a, b>`, equal to the cosine distance for normalized embeddings
+    InnerProduct,
+}
+
+impl Metric {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Metric::Cosine => "cosine",
+            Metric::L2 => "l2",
+            Metric::InnerProduct => "ip",
+        }
+    }
+
+    pub fn distance(&self, a: &[f32], b: &[f32]) -> f32 {
+        match self {
+            Metric::Cosine => DistCosine {}.eval(a, b),
+            Metric::L2 => DistL2 {}.eval(a, b),
+            Metric::InnerProduct => DistInnerProduct {}.eval(a, b),
+        }
+    }
+}
+
+impl Display for Metric {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Metric {
+    type Err = IndexError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "cosine" => Ok(Metric::Cosine),
+            "l2" => Ok(Metric::L2),
+            "ip" => Ok(Metric::InnerProduct),
+            _ => Err(IndexError::ConfigError(format!(
+                "invalid metric {s}: expected one of cosine, l2, ip"
+            ))),
+        }
+    }
+}
+
+/// Kind of index used to search a collection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IndexKind {
+    /// Approximate search on an HNSW graph.
+    #[default]
+    Hnsw,
+    /// Exact search comparing the query against every embedding.
+    Flat,
+    /// Approximate search over the partitions of a k-means coarse quantizer.
+    Ivf,
+}
+
+impl IndexKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            IndexKind::Hnsw => "hnsw",
+            IndexKind::Flat => "flat",
+            IndexKind::Ivf => "ivf",
+        }
+    }
+}
+
+impl Display for IndexKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for IndexKind {
+    type Err = IndexError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "hnsw" => Ok(IndexKind::Hnsw),
+            "flat" => Ok(IndexKind::Flat),
+            "ivf" => Ok(IndexKind::Ivf),
+            _ => Err(IndexError::ConfigError(format!(
+                "invalid index type {s}: expected one of hnsw, flat, ivf"
+            ))),
+        }
+    }
+}
+
+/// How flat and IVF indexes compress the vectors they keep in memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Quantization {
+    /// Vectors are kept as `f32`s.
+    #[default]
+    None,
+    /// Each dimension is stored as one byte (SQ8).
+    Scalar,
+    /// Each subvector is stored as the one byte id of its closest centroid (PQ).
+    Product,
+    /// Each dimension is stored as one bit, and codes are compared by Hamming distance.
+    Binary,
+}
+
+impl Quantization {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Quantization::None => "none",
+            Quantization::Scalar => "sq8",
+            Quantization::Product => "pq",
+            Quantization::Binary => "binary",
+        }
+    }
+}
+
+impl Display for Quantization {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Quantization {
+    type Err = IndexError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Quantization::None),
+            "sq8" => Ok(Quantization::Scalar),
+            "pq" => Ok(Quantization::Product),
+            "binary" => Ok(Quantization::Binary),
+            _ => Err(IndexError::ConfigError(format!(
+                "invalid quantization {s}: expected one of none, sq8, pq, binary"
+            ))),
+        }
+    }
+}
+
+/// Where a flat index keeps its vectors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Storage {
+    /// Vectors are kept on the heap and written to disk when the database is persisted.
+    #[default]
+    Memory,
+    /// Vectors live in a memory-mapped file next to the database, so collections can be
+    /// larger than memory and reopen without reading every vector.
+    ///
+    /// Only flat indexes without quantization support it; HNSW and IVF indexes are rejected.
+    /// The file is only reused on reopen if nothing was written to the collection after the
+    /// last [`crate::db::Db::persist`], otherwise the index is rebuilt from the database.
+    Mmap,
+}
+
+impl Storage {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Storage::Memory => "memory",
+            Storage::Mmap => "mmap",
+        }
+    }
+}
+
+impl Display for Storage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Storage {
+    type Err = IndexError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "memory" => Ok(Storage::Memory),
+            "mmap" => Ok(Storage::Mmap),
+            _ => Err(IndexError::ConfigError(format!(
+                "invalid storage {s}: expected one of memory, mmap"
+            ))),
+        }
+    }
+}
+
+/// `1 - <a, b>`. Unlike `hnsw_rs::dist::DistDot` it does not require normalized vectors.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct DistInnerProduct;
+
+impl Distance<f32> for DistInnerProduct {
+    fn eval(&self, va: &[f32], vb: &[f32]) -> f32 {
+        1.0 - va.iter().zip(vb).map(|(a, b)| a * b).sum::<f32>()
+    }
+}
+
+/// Configuration of a collection's index. It is stored in the collection's metadata under
+/// `index:*`, `hnsw:*`, `ivf:*` and `pq:*` keys. Each kind of index ignores the parameters of
+/// the others.
+#[derive(Debug, Clone, PartialEq, Builder)]
+#[builder(default, build_fn(validate = "Self::validate", error = "IndexError"))]
+pub struct IndexConfig {
+    pub kind: IndexKind,
+    pub metric: Metric,
+    /// Maximum number of links per point (`M`).
+    pub max_connections: usize,
+    /// Size of the candidate list while inserting points.
+    pub ef_construction: usize,
+    /// Number of layers of the graph, at most 16.
+    pub max_layers: usize,
+    /// Expected number of points, used to size the graph up front.
+    pub capacity: usize,
+    /// Size of the candidate list while searching, unless overridden per query.
+    pub ef_search: usize,
+    /// Number of partitions of an IVF index.
+    pub nlist: usize,
+    /// Number of partitions an IVF index searches, unless overridden per query.
+    pub nprobe: usize,
+    /// Compression of the vectors kept by flat and IVF indexes.
+    pub quantization: Quantization,
+    /// Number of subvectors of product quantization codes.
+    pub pq_subvectors: usize,
+    /// With quantization, `rescore * k` candidates are rescored with the full precision
+    /// embeddings to find the `k` nearest neighbours.
+    pub rescore: usize,
+    /// Where the vectors of a flat index are kept.
+    pub storage: Storage,
+}
+
+impl Default for IndexConfig {
+    fn default() -> Self {
+        Self {
+            kind: IndexKind::default(),
+            metric: Metric::default(),
+            max_connections: 16,
+            ef_construction: 200,
+            max_layers: 16,
+            capacity: 100,
+            ef_search: 30,
+            nlist: 100,
+            nprobe: 10,
+            quantization: Quantization::default(),
+            pq_subvectors: 16,
+            rescore: 4,
+            storage: Storage::default(),
+        }
+    }
+}
+
+impl IndexConfigBuilder {
+    fn validate(&self) -> Result<(), IndexError> {
+        let default = IndexConfig::default();
+        let config = IndexConfig {
+            kind: self.kind.unwrap_or(default.kind),
+            metric: self.metric.unwrap_or(default.metric),
+            max_connections: self.max_connections.unwrap_or(default.max_connections),
+            ef_construction: self.ef_construction.unwrap_or(default.ef_construction),
+            max_layers: self.max_layers.unwrap_or(default.max_layers),
+            capacity: self.capacity.unwrap_or(default.capacity),
+            ef_search: self.ef_search.unwrap_or(default.ef_search),
+            nlist: self.nlist.unwrap_or(default.nlist),
+            nprobe: self.nprobe.unwrap_or(default.nprobe),
+            quantization: self.quantization.unwrap_or(default.quantization),
+            pq_subvectors: self.pq_subvectors.unwrap_or(default.pq_subvectors),
+            rescore: self.rescore.unwrap_or(default.rescore),
+            storage: self.storage.unwrap_or(default.storage),
+        };
+
+        config.validate()
+    }
+}
+
+impl From<derive_builder::UninitializedFieldError> for IndexError {
+    fn from(value: derive_builder::UninitializedFieldError) -> Self {
+        IndexError::ConfigError(value.to_string())
+    }
+}
+
+impl IndexConfig {
+    pub fn validate(&self) -> Result<(), IndexError> {
+        let positive = [
+            ("max_connections", self.max_connections),
+            ("ef_construction", self.ef_construction),
+            ("capacity", self.capacity),
+            ("ef_search", self.ef_search),
+            ("nlist", self.nlist),
+            ("nprobe", self.nprobe),
+            ("pq_subvectors", self.pq_subvectors),
+            ("rescore", self.rescore),
+        ];
+        if let Some((name, _)) = positive.iter().find(|(_, value)| *value == 0) {
+            return Err(IndexError::ConfigError(format!("{name} must be positive")));
+        }
+
+        if !(1..=MAX_LAYERS).contains(&self.max_layers) {
+            return Err(IndexError::ConfigError(format!(
+                "max_layers must be between 1 and {MAX_LAYERS}, found {}",
+                self.max_layers
+            )));
+        }
+
+        if self.kind == IndexKind::Hnsw && self.quantization != Quantization::None {
+            return Err(IndexError::ConfigError(format!(
+                "{} quantization is only supported by flat and ivf indexes",
+                self.quantization
+            )));
+        }
+
+        if self.storage == Storage::Mmap
+            && (self.kind != IndexKind::Flat || self.quantization != Quantization::None)
+        {
+            return Err(IndexError::ConfigError(
+                "mmap storage is only supported by flat indexes without quantization".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    pub fn from_metadata(metadata: &Value) -> Result<Self, IndexError> {
+        let mut config = IndexConfig::default();
+
+        if let Some(kind) = metadata.get(KIND_KEY) {
+            config.kind = kind
+                .as_str()
+                .ok_or_else(|| IndexError::ConfigError(format!("invalid {KIND_KEY}: {kind}")))?
+                .parse()?;
+        }
+
+        if let Some(quantization) = metadata.get(QUANTIZATION_KEY) {
+            config.quantization = quantization
+                .as_str()
+                .ok_or_else(|| {
+                    IndexError::ConfigError(format!("invalid {QUANTIZATION_KEY}: {quantization}"))
+                })?
+                .parse()?;
+        }
+
+        if let Some(storage) = metadata.get(STORAGE_KEY) {
+            config.storage = storage
+                .as_str()
+                .ok_or_else(|| {
+                    IndexError::ConfigError(format!("invalid {STORAGE_KEY}: {storage}"))
+                })?
+                .parse()?;
+        }
+
+        if let Some(space) = metadata.get(SPACE_KEY) {
+            config.metric = space
+                .as_str()
+                .ok_or_else(|| IndexError::ConfigError(format!("invalid {SPACE_KEY}: {space}")))?
+                .parse()?;
+        }
+
+        let fields = [
+            (M_KEY, &mut config.max_connections),
+            (CONSTRUCTION_EF_KEY, &mut config.ef_construction),
+            (MAX_LAYERS_KEY, &mut config.max_layers),
+            (CAPACITY_KEY, &mut config.capacity),
+            (SEARCH_EF_KEY, &mut config.ef_search),
+            (NLIST_KEY, &mut config.nlist),
+            (NPROBE_KEY, &mut config.nprobe),
+            (PQ_SUBVECTORS_KEY, &mut config.pq_subvectors),
+            (RESCORE_KEY, &mut config.rescore),
+        ];
+        for (key, field) in fields {
+            if let Some(value) = metadata.get(key) {
+                *field = value
+                    .as_u64()
+                    .ok_or_else(|| IndexError::ConfigError(format!("invalid {key}: {value}")))?
+                    as usize;
+            }
+        }
+
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    pub fn write_metadata(&self, metadata: &mut Map<String, Value>) {
+        metadata.insert(KIND_KEY.to_string(), json!(self.kind.as_str()));
+        metadata.insert(SPACE_KEY.to_string(), json!(self.metric.as_str()));
+        metadata.insert(M_KEY.to_string(), json!(self.max_connections));
+        metadata.insert(CONSTRUCTION_EF_KEY.to_string(), json!(self.ef_construction));
+        metadata.insert(MAX_LAYERS_KEY.to_string(), json!(self.max_layers));
+        metadata.insert(CAPACITY_KEY.to_string(), json!(self.capacity));
+        metadata.insert(SEARCH_EF_KEY.to_string(), json!(self.ef_search));
+        metadata.insert(NLIST_KEY.to_string(), json!(self.nlist));
+        metadata.insert(NPROBE_KEY.to_string(), json!(self.nprobe));
+        metadata.insert(
+            QUANTIZATION_KEY.to_string(),
+            json!(self.quantization.as_str()),
+        );
+        metadata.insert(PQ_SUBVECTORS_KEY.to_string(), json!(self.pq_subvectors));
+        metadata.insert(RESCORE_KEY.to_string(), json!(self.rescore));
+        metadata.insert(STORAGE_KEY.to_string(), json!(self.storage.as_str()));
+    }
+}
@@ -0,0 +1,893 @@
+use std::{
+    borrow::Cow,
+    cmp::min,
+    collections::{HashMap, HashSet},
+    fmt::Debug,
+    fs::File,
+    io::{BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+use crate::{Embedding, QueryOptions};
+
+use super::{
+    mmap::MappedVectors,
+    quantize::{training_points, Quantizer, Score},
+    IndexConfig, IndexEntry, IndexError, Metric, Quantization, Storage, VectorIndex,
+};
+
+// Number of independent accumulators in the dot product. Keeping them separate lets the
+// compiler vectorize the loop without reordering float additions itself.
+const LANES: usize = 8;
+
+/// Index that compares the query against every stored vector.
+///
+/// Vectors are kept in one contiguous row-major buffer together with their squared norms, so
+/// a search is a single pass of dot products over memory. Searches are exact unless the
+/// vectors are quantized, in which case they are compressed to byte or bit codes once enough
+/// of them have been added to train the quantizer. With [`Storage::Mmap`] the buffer is a
+/// memory-mapped file instead, see [`FlatIndex::mapped`].
+pub struct FlatIndex {
+    metric: Metric,
+    quantization: Quantization,
+    subvectors: usize,
+    rescore: usize,
+    dimension: Option<usize>,
+    // Full precision rows, empty once the vectors are quantized or if they are mapped.
+    data: Vec<f32>,
+    // Where mapped vectors are stored, and the mapped files once the dimension is known.
+    basename: Option<PathBuf>,
+    mapped: Option<MappedVectors>,
+    quantizer: Option<Arc<Quantizer>>,
+    codes: Vec<u8>,
+    norms: Vec<f32>,
+    uuids: Vec<Uuid>,
+    slots: HashMap<Uuid, usize>,
+}
+
+impl Debug for FlatIndex {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FlatIndex")
+            .field("metric", &self.metric)
+            .field("quantization", &self.quantization)
+            .field("basename", &self.basename)
+            .field("dimension", &self.dimension)
+            .field("len", &self.len())
+            .finish()
+    }
+}
+
+impl FlatIndex {
+    /// Creates an index that keeps its vectors in memory, whatever `config.storage`.
+    pub fn new(config: &IndexConfig) -> Self {
+        Self::with_quantizer(config, None)
+    }
+
+    /// Creates an index that keeps its vectors in memory-mapped files starting with
+    /// `basename`, replacing any existing ones. The files are created on the first `add`.
+    pub fn mapped(config: &IndexConfig, basename: &Path) -> Result<Self, IndexError> {
+        if config.quantization != Quantization::None {
+            return Err(IndexError::ConfigError(
+                "mapped vectors cannot be quantized".to_string(),
+            ));
+        }
+
+        MappedVectors::remove(basename)?;
+
+        let mut idx = Self::new(config);
+        idx.basename = Some(basename.to_path_buf());
+
+        Ok(idx)
+    }
+
+    /// Creates an index whose vectors are encoded with an already trained `quantizer`.
+    pub(crate) fn with_quantizer(config: &IndexConfig, quantizer: Option<Arc<Quantizer>>) -> Self {
+        Self {
+            metric: config.metric,
+            quantization: config.quantization,
+            subvectors: config.pq_subvectors,
+            rescore: config.rescore,
+            dimension: None,
+            data: Vec::new(),
+            basename: None,
+            mapped: None,
+            quantizer,
+            codes: Vec::new(),
+            norms: Vec::with_capacity(config.capacity),
+            uuids: Vec::with_capacity(config.capacity),
+            slots: HashMap::with_capacity(config.capacity),
+        }
+    }
+
+    /// Rebuilds an index from the uuids and the rows written by [`FlatIndex::write_rows`].
+    pub(crate) fn from_rows(
+        config: &IndexConfig,
+        quantizer: Option<Arc<Quantizer>>,
+        dimension: Option<usize>,
+        uuids: Vec<Uuid>,
+        bytes: &[u8],
+    ) -> Result<Self, IndexError> {
+        let row_bytes = row_bytes(quantizer.as_deref(), dimension.unwrap_or(0));
+        if bytes.len() != uuids.len() * row_bytes {
+            return Err(IndexError::LoadError(
+                format!(
+                    "expected {} bytes of vectors, found {}",
+                    uuids.len() * row_bytes,
+                    bytes.len()
+                )
+                .into(),
+            ));
+        }
+
+        let mut idx = Self::with_quantizer(config, quantizer);
+        idx.dimension = dimension;
+        if idx.quantizer.is_some() {
+            idx.codes = bytes.to_vec();
+        } else {
+            idx.data = bytes
+                .chunks_exact(4)
+                .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                .collect();
+        }
+        idx.slots = uuids
+            .iter()
+            .enumerate()
+            .map(|(slot, uuid)| (*uuid, slot))
+            .collect();
+        idx.uuids = uuids;
+        idx.norms = (0..idx.len()).map(|slot| sq_norm(&idx.row(slot))).collect();
+
+        Ok(idx)
+    }
+
+    /// Deletes the files written by [`FlatIndex::dump`], if any.
+    pub fn remove_dump(basename: &Path) -> Result<(), IndexError> {
+        let basename = basename.to_string_lossy().to_string();
+
+        for suffix in [".flat.json", ".flat.data"] {
+            let path = format!("{basename}{suffix}");
+            if Path::new(&path).exists() {
+                std::fs::remove_file(path)?;
+            }
+        }
+
+        MappedVectors::remove(Path::new(&basename))
+    }
+
+    /// Loads an index previously written by [`FlatIndex::dump`]. Returns `None` if no dump
+    /// exists. Fails if the dump was written with a different configuration than `config`.
+    ///
+    /// Mapped vectors are not read: only their uuids and norms are.
+    pub fn load(basename: &Path, config: &IndexConfig) -> Result<Option<Self>, IndexError> {
+        let path = basename;
+        let basename = basename.to_string_lossy().to_string();
+
+        let meta_path = format!("{basename}.flat.json");
+        let data_path = format!("{basename}.flat.data");
+
+        if !Path::new(&meta_path).exists() {
+            return Ok(None);
+        }
+
+        let meta: Value = serde_json::from_reader(BufReader::new(File::open(&meta_path)?))
+            .map_err(|e| IndexError::LoadError(e.into()))?;
+
+        let invalid = || IndexError::LoadError(format!("malformed index in {meta_path}").into());
+
+        let metric: Metric = meta["metric"].as_str().ok_or_else(invalid)?.parse()?;
+        if metric != config.metric {
+            return Err(IndexError::LoadError(
+                format!(
+                    "index was dumped with metric {metric}, expected {}",
+                    config.metric
+                )
+                .into(),
+            ));
+        }
+
+        let dimension = meta["dimension"].as_u64().map(|d| d as usize);
+
+        let storage: Storage = meta["storage"].as_str().unwrap_or("memory").parse()?;
+        if storage != config.storage {
+            return Err(IndexError::LoadError(
+                format!(
+                    "index was dumped with {storage} storage, expected {}",
+                    config.storage
+                )
+                .into(),
+            ));
+        }
+
+        if storage == Storage::Mmap {
+            let mut idx = Self::new(config);
+            idx.basename = Some(path.to_path_buf());
+            idx.mapped = dimension
+                .map(|dimension| MappedVectors::open(path, dimension))
+                .transpose()?;
+            idx.dimension = dimension;
+
+            if let Some(mapped) = &idx.mapped {
+                for slot in 0..mapped.len() {
+                    let (uuid, norm) = mapped.entry(slot);
+                    idx.slots.insert(uuid, slot);
+                    idx.uuids.push(uuid);
+                    idx.norms.push(norm);
+                }
+            }
+
+            return Ok(Some(idx));
+        }
+
+        if !Path::new(&data_path).exists() {
+            return Ok(None);
+        }
+
+        let quantizer = load_quantizer(&meta["quantizer"])?;
+        let uuids = meta["uuids"]
+            .as_array()
+            .ok_or_else(invalid)?
+            .iter()
+            .map(|uuid| uuid.as_str().and_then(|s| s.parse().ok()))
+            .collect::<Option<Vec<Uuid>>>()
+            .ok_or_else(invalid)?;
+
+        let mut bytes = vec![];
+        BufReader::new(File::open(&data_path)?).read_to_end(&mut bytes)?;
+
+        Self::from_rows(config, quantizer, dimension, uuids, &bytes).map(Some)
+    }
+
+    pub(crate) fn entries(&self) -> impl Iterator<Item = (&Uuid, Cow<'_, [f32]>)> {
+        self.uuids
+            .iter()
+            .enumerate()
+            .map(|(slot, uuid)| (uuid, self.row(slot)))
+    }
+
+    /// Writes the stored rows, either quantized codes or little-endian `f32`s.
+    pub(crate) fn write_rows(&self, out: &mut impl Write) -> Result<(), IndexError> {
+        if self.quantizer.is_some() {
+            out.write_all(&self.codes)?;
+        } else {
+            for slot in 0..self.len() {
+                for x in self.full_row(slot) {
+                    out.write_all(&x.to_le_bytes())?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn row(&self, slot: usize) -> Cow<'_, [f32]> {
+        match &self.quantizer {
+            Some(quantizer) => Cow::Owned(quantizer.decode(self.code(slot))),
+            None => Cow::Borrowed(self.full_row(slot)),
+        }
+    }
+
+    fn full_row(&self, slot: usize) -> &[f32] {
+        if let Some(mapped) = &self.mapped {
+            return mapped.vector(slot);
+        }
+
+        let dimension = self.dimension.unwrap_or(0);
+        &self.data[slot * dimension..(slot + 1) * dimension]
+    }
+
+    fn code(&self, slot: usize) -> &[u8] {
+        let size = self.row_bytes();
+        &self.codes[slot * size..(slot + 1) * size]
+    }
+
+    fn row_bytes(&self) -> usize {
+        row_bytes(self.quantizer.as_deref(), self.dimension.unwrap_or(0))
+    }
+
+    // Stores `v` in `slot`, or appends it if `slot` is past the end, and returns the squared
+    // norm of the stored vector.
+    fn store(&mut self, slot: usize, uuid: Uuid, v: &[f32]) -> Result<f32, IndexError> {
+        if let Some(mapped) = &mut self.mapped {
+            let norm = sq_norm(v);
+            if slot == self.uuids.len() {
+                mapped.push(uuid, v, norm)?;
+            } else {
+                mapped.set(slot, uuid, v, norm);
+            }
+            return Ok(norm);
+        }
+
+        let Some(quantizer) = &self.quantizer else {
+            let dimension = v.len();
+            if slot == self.uuids.len() {
+                self.data.extend_from_slice(v);
+            } else {
+                self.data[slot * dimension..(slot + 1) * dimension].copy_from_slice(v);
+            }
+            return Ok(sq_norm(v));
+        };
+
+        let mut code = Vec::with_capacity(quantizer.code_size());
+        quantizer.encode(v, &mut code);
+        let norm = sq_norm(&quantizer.decode(&code));
+
+        let size = code.len();
+        if slot == self.uuids.len() {
+            self.codes.extend_from_slice(&code);
+        } else {
+            self.codes[slot * size..(slot + 1) * size].copy_from_slice(&code);
+        }
+
+        Ok(norm)
+    }
+
+    // Trains the quantizer on the stored vectors and replaces them with their codes.
+    fn quantize(&mut self) {
+        let dimension = self.dimension.unwrap_or(0).max(1);
+        let vectors: Vec<&[f32]> = self.data.chunks_exact(dimension).collect();
+
+        let Some(quantizer) = Quantizer::train(self.quantization, &vectors, self.subvectors) else {
+            return;
+        };
+
+        let size = quantizer.code_size();
+        let mut codes = Vec::with_capacity(self.uuids.capacity() * size);
+        for (slot, v) in vectors.iter().enumerate() {
+            quantizer.encode(v, &mut codes);
+            self.norms[slot] = sq_norm(&quantizer.decode(&codes[slot * size..]));
+        }
+
+        self.codes = codes;
+        self.data = Vec::new();
+        self.quantizer = Some(Arc::new(quantizer));
+    }
+
+    // Moves the last row into `slot` so that the buffer stays contiguous.
+    fn remove_slot(&mut self, slot: usize) {
+        let size = match self.quantizer {
+            Some(_) => self.row_bytes(),
+            None => self.dimension.unwrap_or(0),
+        };
+        let last = self.uuids.len() - 1;
+
+        if let Some(mapped) = &mut self.mapped {
+            mapped.swap_remove(slot);
+        } else if slot != last {
+            match self.quantizer {
+                Some(_) => self
+                    .codes
+                    .copy_within(last * size..(last + 1) * size, slot * size),
+                None => self
+                    .data
+                    .copy_within(last * size..(last + 1) * size, slot * size),
+            }
+        }
+
+        if slot != last {
+            self.norms[slot] = self.norms[last];
+            self.uuids[slot] = self.uuids[last];
+            self.slots.insert(self.uuids[slot], slot);
+        }
+
+        self.codes.truncate(last * size);
+        self.data.truncate(last * size);
+        self.norms.truncate(last);
+        self.uuids.truncate(last);
+    }
+
+    fn distance(&self, dot: f32, query_norm: f32, slot: usize) -> f32 {
+        match self.metric {
+            Metric::Cosine => {
+                let norms = query_norm * self.norms[slot];
+                if norms > 0.0 {
+                    (1.0 - dot / norms.sqrt()).max(0.0)
+                } else {
+                    0.0
+                }
+            }
+            Metric::L2 => (query_norm + self.norms[slot] - 2.0 * dot).max(0.0).sqrt(),
+            Metric::InnerProduct => 1.0 - dot,
+        }
+    }
+}
+
+impl VectorIndex for FlatIndex {
+    fn metric(&self) -> Metric {
+        self.metric
+    }
+
+    fn add(&mut self, entry: IndexEntry) -> Result<(), IndexError> {
+        let v = entry.e.get();
+
+        match self.dimension {
+            Some(dimension) if dimension != v.len() => {
+                return Err(IndexError::DimensionError {
+                    expected: dimension,
+                    found: v.len(),
+                })
+            }
+            Some(_) => {}
+            None => {
+                if let Some(basename) = &self.basename {
+                    self.mapped = Some(MappedVectors::create(basename, v.len())?);
+                } else if self.quantizer.is_none() {
+                    self.data.reserve(self.uuids.capacity() * v.len());
+                }
+                self.dimension = Some(v.len());
+            }
+        }
+
+        if let Some(&slot) = self.slots.get(&entry.uuid) {
+            self.norms[slot] = self.store(slot, entry.uuid, v)?;
+            return Ok(());
+        }
+
+        let norm = self.store(self.uuids.len(), entry.uuid, v)?;
+        self.slots.insert(entry.uuid, self.uuids.len());
+        self.uuids.push(entry.uuid);
+        self.norms.push(norm);
+
+        if self.quantizer.is_none()
+            && self.quantization != Quantization::None
+            && self.len() >= training_points(self.quantization)
+        {
+            self.quantize();
+        }
+
+        Ok(())
+    }
+
+    fn remove(&mut self, uuids: &HashSet<Uuid>) -> usize {
+        let mut removed = 0;
+
+        for uuid in uuids {
+            if let Some(slot) = self.slots.remove(uuid) {
+                self.remove_slot(slot);
+                removed += 1;
+            }
+        }
+
+        removed
+    }
+
+    fn len(&self) -> usize {
+        self.uuids.len()
+    }
+
+    fn dimension(&self) -> Option<usize> {
+        self.dimension
+    }
+
+    fn uuids(&self) -> Box<dyn Iterator<Item = &Uuid> + '_> {
+        Box::new(self.uuids.iter())
+    }
+
+    fn is_exact(&self) -> bool {
+        self.quantization == Quantization::None
+    }
+
+    fn rescore_factor(&self) -> Option<usize> {
+        (self.quantization != Quantization::None).then_some(self.rescore)
+    }
+
+    /// Writes the uuids to `<basename>.flat.json` and the vectors to `<basename>.flat.data`.
+    /// Mapped vectors are flushed to their files instead, which must start with `basename`.
+    fn dump(&self, basename: &Path) -> Result<(), IndexError> {
+        if let Some(mapped_basename) = &self.basename {
+            if mapped_basename != basename {
+                return Err(IndexError::DumpError(
+                    format!(
+                        "vectors are mapped from {}, cannot dump them to {}",
+                        mapped_basename.display(),
+                        basename.display()
+                    )
+                    .into(),
+                ));
+            }
+
+            if let Some(mapped) = &self.mapped {
+                mapped.flush()?;
+            }
+
+            let file = File::create(format!("{}.flat.json", basename.display()))?;
+            return serde_json::to_writer(
+                BufWriter::new(file),
+                &json!({
+                    "metric": self.metric.as_str(),
+                    "dimension": self.dimension,
+                    "storage": Storage::Mmap.as_str(),
+                }),
+            )
+            .map_err(|e| IndexError::DumpError(e.into()));
+        }
+
+        let basename = basename.to_string_lossy().to_string();
+
+        let uuids: Vec<String> = self.uuids.iter().map(|uuid| uuid.to_string()).collect();
+
+        let file = File::create(format!("{basename}.flat.json"))?;
+        serde_json::to_writer(
+            BufWriter::new(file),
+            &json!({
+                "metric": self.metric.as_str(),
+                "dimension": self.dimension,
+                "quantizer": self.quantizer.as_ref().map(|q| q.to_json()),
+                "uuids": uuids,
+            }),
+        )
+        .map_err(|e| IndexError::DumpError(e.into()))?;
+
+        let mut data_out = BufWriter::new(File::create(format!("{basename}.flat.data"))?);
+        self.write_rows(&mut data_out)?;
+        data_out.flush()?;
+
+        Ok(())
+    }
+
+    /// `options` are ignored: every stored vector is compared against the query.
+    fn search(
+        &self,
+        embedding: &Embedding,
+        k: usize,
+        _options: &QueryOptions,
+        filter: &dyn Fn(&Uuid) -> bool,
+    ) -> Vec<(Uuid, f32)> {
+        let query = embedding.get();
+        if k == 0 || self.dimension != Some(query.len()) {
+            return vec![];
+        }
+
+        let query_norm = sq_norm(query);
+        let table = self.quantizer.as_ref().map(|q| q.query_table(query));
+
+        // Binary codes are ranked by Hamming distance, which is only meaningful until the
+        // candidates are rescored.
+        let mut neighbours: Vec<_> = self
+            .uuids
+            .iter()
+            .enumerate()
+            .filter(|(_, uuid)| filter(uuid))
+            .map(|(slot, uuid)| {
+                let distance = match table.as_ref().map(|t| t.score(self.code(slot))) {
+                    Some(Score::Dot(dot)) => self.distance(dot, query_norm, slot),
+                    Some(Score::Hamming(bits)) => bits as f32,
+                    None => self.distance(dot(query, self.full_row(slot)), query_norm, slot),
+                };
+                (*uuid, distance)
+            })
+            .collect();
+
+        let k = min(k, neighbours.len());
+        if k == 0 {
+            return neighbours;
+        }
+
+        neighbours.select_nth_unstable_by(k - 1, |a, b| a.1.total_cmp(&b.1));
+        neighbours.truncate(k);
+        neighbours.sort_by(|a, b| a.1.total_cmp(&b.1));
+
+        neighbours
+    }
+}
+
+/// Number of bytes a row takes in [`FlatIndex::write_rows`].
+pub(crate) fn row_bytes(quantizer: Option<&Quantizer>, dimension: usize) -> usize {
+    match quantizer {
+        Some(quantizer) => quantizer.code_size(),
+        None => dimension * 4,
+    }
+}
+
+pub(crate) fn load_quantizer(value: &Value) -> Result<Option<Arc<Quantizer>>, IndexError> {
+    if value.is_null() {
+        return Ok(None);
+    }
+
+    Quantizer::from_json(value).map(|q| Some(Arc::new(q)))
+}
+
+pub(crate) fn dot(a: &[f32], b: &[f32]) -> f32 {
+    let mut acc = [0.0f32; LANES];
+
+    let chunks_a = a.chunks_exact(LANES);
+    let chunks_b = b.chunks_exact(LANES);
+    let tail: f32 = chunks_a
+        .remainder()
+        .iter()
+        .zip(chunks_b.remainder())
+        .map(|(x, y)| x * y)
+        .sum();
+
+    for (ca, cb) in chunks_a.zip(chunks_b) {
+        for ((acc, x), y) in acc.iter_mut().zip(ca).zip(cb) {
+            *acc += x * y;
+        }
+    }
+
+    acc.iter().sum::<f32>() + tail
+}
+
+fn sq_norm(v: &[f32]) -> f32 {
+    dot(v, v)
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+
+    use uuid::Uuid;
+
+    use crate::{
+        index::{
+            random_vectors, IndexConfig, IndexEntry, IndexError, IndexKind, Metric, Quantization,
+            Storage, VectorIndex,
+        },
+        Embedding, QueryOptions,
+    };
+
+    use super::FlatIndex;
+
+    fn entry(v: &[f32]) -> IndexEntry {
+        IndexEntry {
+            e: Embedding::new(v.to_vec()),
+            uuid: Uuid::new_v4(),
+        }
+    }
+
+    #[test]
+    pub fn test_flat_search_matches_metric() {
+        for metric in [Metric::Cosine, Metric::L2, Metric::InnerProduct] {
+            let config = IndexConfig {
+                metric,
+                ..Default::default()
+            };
+            let mut idx = FlatIndex::new(&config);
+
+            let vectors: Vec<Vec<f32>> = (0..50)
+                .map(|i| {
+                    (0..19)
+                        .map(|j| ((i * 7 + j * 3) % 11) as f32 / 11.0)
+                        .collect()
+                })
+                .collect();
+            let uuids: Vec<_> = vectors.iter().map(|_| Uuid::new_v4()).collect();
+            for (v, uuid) in vectors.iter().zip(&uuids) {
+                idx.add(IndexEntry {
+                    e: Embedding::new(v.clone()),
+                    uuid: *uuid,
+                })
+                .unwrap();
+            }
+
+            let query = Embedding::new((0..19).map(|j| (j % 5) as f32 / 5.0).collect());
+            let neighbours = idx.search(&query, 5, &QueryOptions::default(), &|_| true);
+
+            let mut expected: Vec<_> = uuids
+                .iter()
+                .zip(&vectors)
+                .map(|(uuid, v)| (*uuid, metric.distance(query.get(), v)))
+                .collect();
+            expected.sort_by(|a, b| a.1.total_cmp(&b.1));
+
+            assert_eq!(neighbours.len(), 5);
+            for ((uuid, dist), (expected_uuid, expected_dist)) in neighbours.iter().zip(&expected) {
+                assert!((dist - expected_dist).abs() < 1e-4, "{metric}");
+                if (dist - expected_dist).abs() > 1e-6 {
+                    assert_eq!(uuid, expected_uuid);
+                }
+            }
+        }
+    }
+
+    #[test]
+    pub fn test_flat_remove() {
+        let mut idx = FlatIndex::new(&IndexConfig::default());
+
+        let entries: Vec<_> = (1..=4).map(|i| entry(&[i as f32, 1.0])).collect();
+        let uuids: Vec<_> = entries.iter().map(|e| e.uuid).collect();
+        for e in entries {
+            idx.add(e).unwrap();
+        }
+
+        assert_eq!(idx.remove(&HashSet::from([uuids[0], Uuid::new_v4()])), 1);
+        assert_eq!(idx.len(), 3);
+
+        let found: HashSet<_> = idx
+            .search(
+                &Embedding::new(vec![1.0, 1.0]),
+                10,
+                &QueryOptions::default(),
+                &|_| true,
+            )
+            .into_iter()
+            .map(|(uuid, _)| uuid)
+            .collect();
+        assert_eq!(found, uuids[1..].iter().copied().collect());
+
+        let filtered = idx.search(
+            &Embedding::new(vec![4.0, 1.0]),
+            10,
+            &QueryOptions::default(),
+            &|uuid| *uuid != uuids[3],
+        );
+        assert_eq!(filtered.len(), 2);
+        assert_eq!(filtered[0].0, uuids[2]);
+    }
+
+    #[test]
+    pub fn test_flat_dimension_mismatch() {
+        let mut idx = FlatIndex::new(&IndexConfig::default());
+
+        idx.add(entry(&[1.0, 2.0, 3.0])).unwrap();
+
+        assert!(matches!(
+            idx.add(entry(&[1.0, 2.0])),
+            Err(IndexError::DimensionError {
+                expected: 3,
+                found: 2
+            })
+        ));
+    }
+
+    #[test]
+    pub fn test_flat_add_existing_uuid() {
+        let mut idx = FlatIndex::new(&IndexConfig::default());
+
+        let first = entry(&[1.0, 0.0]);
+        let uuid = first.uuid;
+        idx.add(first).unwrap();
+        idx.add(entry(&[0.0, 1.0])).unwrap();
+        idx.add(IndexEntry {
+            e: Embedding::new(vec![0.6, 0.8]),
+            uuid,
+        })
+        .unwrap();
+
+        assert_eq!(idx.len(), 2);
+
+        let res = idx.search(
+            &Embedding::new(vec![1.0, 0.0]),
+            3,
+            &QueryOptions::default(),
+            &|_| true,
+        );
+        assert_eq!(res.len(), 2);
+        assert_eq!(res[0].0, uuid);
+        assert!((res[0].1 - 0.4).abs() < 1e-5);
+    }
+
+    #[test]
+    pub fn test_flat_dump_and_load() {
+        let basename = std::env::temp_dir().join(format!("cedar-flat-{}", Uuid::new_v4()));
+        let config = IndexConfig {
+            metric: Metric::L2,
+            ..Default::default()
+        };
+
+        let mut idx = FlatIndex::new(&config);
+        for i in 0..10 {
+            idx.add(entry(&[i as f32, 0.5, -1.0])).unwrap();
+        }
+        idx.dump(&basename).unwrap();
+
+        let loaded = FlatIndex::load(&basename, &config).unwrap().unwrap();
+        FlatIndex::remove_dump(&basename).unwrap();
+
+        let query = Embedding::new(vec![3.2, 0.5, -1.0]);
+        assert_eq!(
+            idx.search(&query, 3, &QueryOptions::default(), &|_| true),
+            loaded.search(&query, 3, &QueryOptions::default(), &|_| true)
+        );
+        assert!(FlatIndex::load(&basename, &config).unwrap().is_none());
+    }
+
+    #[test]
+    pub fn test_flat_mapped() {
+        let basename = std::env::temp_dir()
+            .join(format!("cedar-{}", Uuid::new_v4()))
+            .join("flat");
+        let config = IndexConfig {
+            kind: IndexKind::Flat,
+            metric: Metric::L2,
+            storage: Storage::Mmap,
+            ..Default::default()
+        };
+
+        let mut memory = FlatIndex::new(&config);
+        let mut mapped = FlatIndex::mapped(&config, &basename).unwrap();
+
+        // enough entries to grow the files past their initial size
+        let entries: Vec<_> = (0..3000)
+            .map(|i| entry(&[(i % 97) as f32, (i % 13) as f32, i as f32 / 3000.0]))
+            .collect();
+        let uuids: Vec<_> = entries.iter().map(|e| e.uuid).collect();
+        for e in entries {
+            memory
+                .add(IndexEntry {
+                    e: Embedding::new(e.e.get().clone()),
+                    uuid: e.uuid,
+                })
+                .unwrap();
+            mapped.add(e).unwrap();
+        }
+
+        let removed: HashSet<_> = uuids.iter().step_by(7).copied().collect();
+        assert_eq!(memory.remove(&removed), removed.len());
+        assert_eq!(mapped.remove(&removed), removed.len());
+        assert!(mapped.data.is_empty());
+
+        let query = Embedding::new(vec![40.0, 6.0, 0.5]);
+        let expected = memory.search(&query, 10, &QueryOptions::default(), &|_| true);
+        assert_eq!(
+            mapped.search(&query, 10, &QueryOptions::default(), &|_| true),
+            expected
+        );
+
+        mapped.dump(&basename).unwrap();
+        assert!(mapped.dump(&basename.with_file_name("other")).is_err());
+        drop(mapped);
+
+        let loaded = FlatIndex::load(&basename, &config).unwrap().unwrap();
+        assert_eq!(loaded.len(), uuids.len() - removed.len());
+        assert_eq!(
+            loaded.search(&query, 10, &QueryOptions::default(), &|_| true),
+            expected
+        );
+        assert!(FlatIndex::load(&basename, &IndexConfig::default()).is_err());
+
+        FlatIndex::remove_dump(&basename).unwrap();
+        assert!(FlatIndex::load(&basename, &config).unwrap().is_none());
+        std::fs::remove_dir_all(basename.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    pub fn test_flat_quantized() {
+        let vectors = random_vectors(3000, 32, 7);
+
+        for quantization in [
+            Quantization::Scalar,
+            Quantization::Product,
+            Quantization::Binary,
+        ] {
+            let config = IndexConfig {
+                metric: Metric::L2,
+                quantization,
+                pq_subvectors: 8,
+                ..Default::default()
+            };
+            let mut idx = FlatIndex::new(&config);
+            for v in &vectors {
+                idx.add(entry(v)).unwrap();
+            }
+
+            assert!(idx.quantizer.is_some());
+            assert!(idx.data.is_empty());
+            assert!(!idx.is_exact());
+            assert_eq!(idx.rescore_factor(), Some(config.rescore));
+
+            let query = Embedding::new(vectors[11].clone());
+            let neighbours = idx.search(&query, 10, &QueryOptions::default(), &|_| true);
+            assert_eq!(neighbours.len(), 10);
+
+            if quantization == Quantization::Binary {
+                assert_eq!(neighbours[0], (idx.uuids[11], 0.0));
+                assert!(neighbours.windows(2).all(|w| w[0].1 <= w[1].1));
+                continue;
+            }
+
+            // distances are exact with respect to the decoded vectors
+            for (uuid, dist) in &neighbours {
+                let decoded = idx.row(idx.slots[uuid]);
+                assert!((dist - Metric::L2.distance(query.get(), &decoded)).abs() < 1e-3);
+            }
+
+            if quantization == Quantization::Scalar {
+                assert_eq!(neighbours[0].0, idx.uuids[11]);
+            }
+        }
+    }
+}
@@ -0,0 +1,382 @@
+use std::{
+    cmp::{max, min},
+    collections::{HashMap, HashSet},
+    fmt::Debug,
+    fs::File,
+    io::{BufReader, BufWriter},
+    path::Path,
+};
+
+use hnsw_rs::{
+    hnsw::Hnsw,
+    hnswio::{load_description, load_hnsw},
+    prelude::*,
+};
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+use crate::{Embedding, QueryOptions};
+
+use super::{DistInnerProduct, IndexConfig, IndexEntry, IndexError, Metric, VectorIndex};
+
+enum Graph {
+    Cosine(Hnsw<f32, DistCosine>),
+    L2(Hnsw<f32, DistL2>),
+    InnerProduct(Hnsw<f32, DistInnerProduct>),
+}
+
+// Runs `$body` with `$hnsw` bound to the graph, whatever its distance type.
+macro_rules! with_graph {
+    ($graph:expr, $hnsw:ident => $body:expr) => {
+        match $graph {
+            Graph::Cosine($hnsw) => $body,
+            Graph::L2($hnsw) => $body,
+            Graph::InnerProduct($hnsw) => $body,
+        }
+    };
+}
+
+/// Approximate index backed by an HNSW graph.
+pub struct HnswIndex {
+    idx: Graph,
+    metric: Metric,
+    ef_search: usize,
+    id_to_uuid: HashMap<usize, uuid::Uuid>,
+    // live id of each uuid, so that re-added uuids hide their previous point
+    uuid_to_id: HashMap<Uuid, usize>,
+    last_id: usize,
+    // dimension of the points in the graph, `None` until the first one is added
+    dimension: Option<usize>,
+}
+
+impl Debug for HnswIndex {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("HnswIndex")
+            .field("metric", &self.metric)
+            .field("len", &self.len())
+            .field("dimension", &self.dimension)
+            .finish()
+    }
+}
+
+impl HnswIndex {
+    pub fn new(config: &IndexConfig) -> Self {
+        let IndexConfig {
+            max_connections: m,
+            capacity,
+            max_layers,
+            ef_construction,
+            ..
+        } = *config;
+
+        let idx = match config.metric {
+            Metric::Cosine => Graph::Cosine(Hnsw::new(
+                m,
+                capacity,
+                max_layers,
+                ef_construction,
+                DistCosine {},
+            )),
+            Metric::L2 => Graph::L2(Hnsw::new(
+                m,
+                capacity,
+                max_layers,
+                ef_construction,
+                DistL2 {},
+            )),
+            Metric::InnerProduct => Graph::InnerProduct(Hnsw::new(
+                m,
+                capacity,
+                max_layers,
+                ef_construction,
+                DistInnerProduct {},
+            )),
+        };
+        let id_to_uuid = HashMap::new();
+        let uuid_to_id = HashMap::new();
+        let last_id = 0;
+
+        Self {
+            idx,
+            metric: config.metric,
+            ef_search: config.ef_search,
+            id_to_uuid,
+            uuid_to_id,
+            last_id,
+            dimension: None,
+        }
+    }
+
+    /// Deletes the files written by [`HnswIndex::dump`], if any.
+    pub fn remove_dump(basename: &Path) -> Result<(), IndexError> {
+        let basename = basename.to_string_lossy().to_string();
+
+        for suffix in [".hnsw.graph", ".hnsw.data", ".ids.json"] {
+            let path = format!("{basename}{suffix}");
+            if Path::new(&path).exists() {
+                std::fs::remove_file(path)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Loads an index previously written by [`HnswIndex::dump`]. Returns `None` if no dump
+    /// exists. Fails if the dump was written with a different configuration than `config`.
+    pub fn load(basename: &Path, config: &IndexConfig) -> Result<Option<Self>, IndexError> {
+        let basename = basename.to_string_lossy().to_string();
+
+        let graph_path = format!("{basename}.hnsw.graph");
+        let data_path = format!("{basename}.hnsw.data");
+        let ids_path = format!("{basename}.ids.json");
+
+        if [&graph_path, &data_path, &ids_path]
+            .iter()
+            .any(|p| !Path::new(p).exists())
+        {
+            return Ok(None);
+        }
+
+        let ids: Value = serde_json::from_reader(BufReader::new(File::open(&ids_path)?))
+            .map_err(|e| IndexError::LoadError(e.into()))?;
+
+        let invalid = || IndexError::LoadError(format!("malformed id map in {ids_path}").into());
+
+        let metric: Metric = ids["metric"].as_str().ok_or_else(invalid)?.parse()?;
+        if metric != config.metric {
+            return Err(IndexError::LoadError(
+                format!(
+                    "index was dumped with metric {metric}, expected {}",
+                    config.metric
+                )
+                .into(),
+            ));
+        }
+
+        let mut graph_in = BufReader::new(File::open(&graph_path)?);
+        let mut data_in = BufReader::new(File::open(&data_path)?);
+
+        let description =
+            load_description(&mut graph_in).map_err(|e| IndexError::LoadError(e.into()))?;
+        let idx = match metric {
+            Metric::Cosine => {
+                load_hnsw(&mut graph_in, &description, &mut data_in).map(Graph::Cosine)
+            }
+            Metric::L2 => load_hnsw(&mut graph_in, &description, &mut data_in).map(Graph::L2),
+            Metric::InnerProduct => {
+                load_hnsw(&mut graph_in, &description, &mut data_in).map(Graph::InnerProduct)
+            }
+        }
+        .map_err(|e| IndexError::LoadError(e.into()))?;
+
+        let last_id = ids["last_id"].as_u64().ok_or_else(invalid)? as usize;
+        let dimension = ids["dimension"].as_u64().map(|d| d as usize);
+        let mut id_to_uuid = HashMap::new();
+        for entry in ids["ids"].as_array().ok_or_else(invalid)? {
+            let id = entry[0].as_u64().ok_or_else(invalid)? as usize;
+            let uuid = entry[1]
+                .as_str()
+                .and_then(|s| s.parse().ok())
+                .ok_or_else(invalid)?;
+            id_to_uuid.insert(id, uuid);
+        }
+
+        Ok(Some(Self {
+            idx,
+            metric,
+            ef_search: config.ef_search,
+            uuid_to_id: id_to_uuid.iter().map(|(&id, &uuid)| (uuid, id)).collect(),
+            id_to_uuid,
+            last_id,
+            dimension,
+        }))
+    }
+}
+
+impl VectorIndex for HnswIndex {
+    fn metric(&self) -> Metric {
+        self.metric
+    }
+
+    fn add(&mut self, entry: IndexEntry) -> Result<(), IndexError> {
+        let found = entry.e.dim();
+        match self.dimension {
+            Some(expected) if expected != found => {
+                return Err(IndexError::DimensionError { expected, found })
+            }
+            Some(_) => {}
+            None => self.dimension = Some(found),
+        }
+
+        let id = self.last_id + 1;
+        self.last_id = id;
+        self.id_to_uuid.insert(id, entry.uuid);
+        if let Some(previous) = self.uuid_to_id.insert(entry.uuid, id) {
+            self.id_to_uuid.remove(&previous);
+        }
+        with_graph!(&self.idx, hnsw => hnsw.insert((entry.e.get(), id)));
+
+        Ok(())
+    }
+
+    /// Removes the given uuids from the index. Their points stay in the HNSW graph but are
+    /// never returned by searches again, see [`VectorIndex::tombstones`]. Returns the number
+    /// of removed entries.
+    fn remove(&mut self, uuids: &HashSet<Uuid>) -> usize {
+        uuids
+            .iter()
+            .filter_map(|uuid| self.uuid_to_id.remove(uuid))
+            .filter(|id| self.id_to_uuid.remove(id).is_some())
+            .count()
+    }
+
+    fn len(&self) -> usize {
+        self.id_to_uuid.len()
+    }
+
+    fn dimension(&self) -> Option<usize> {
+        self.dimension
+    }
+
+    fn tombstones(&self) -> usize {
+        self.last_id - self.id_to_uuid.len()
+    }
+
+    fn uuids(&self) -> Box<dyn Iterator<Item = &Uuid> + '_> {
+        Box::new(self.id_to_uuid.values())
+    }
+
+    /// Writes the HNSW graph, its data and the id mapping to `<basename>.hnsw.graph`,
+    /// `<basename>.hnsw.data` and `<basename>.ids.json` respectively.
+    fn dump(&self, basename: &Path) -> Result<(), IndexError> {
+        let basename = basename.to_string_lossy().to_string();
+
+        with_graph!(&self.idx, hnsw => hnsw.file_dump(&basename))
+            .map_err(|e| IndexError::DumpError(e.into()))?;
+
+        let ids: Vec<Value> = self
+            .id_to_uuid
+            .iter()
+            .map(|(id, uuid)| json!([id, uuid.to_string()]))
+            .collect();
+
+        let file = File::create(format!("{basename}.ids.json"))?;
+        serde_json::to_writer(
+            BufWriter::new(file),
+            &json!({
+                "metric": self.metric.as_str(),
+                "last_id": self.last_id,
+                "dimension": self.dimension,
+                "ids": ids,
+            }),
+        )
+        .map_err(|e| IndexError::DumpError(e.into()))?;
+
+        Ok(())
+    }
+
+    /// The candidate pool is doubled until `k` neighbours pass the filter or the whole graph
+    /// has been searched, so selective filters still get `k` results when HNSW finds them.
+    fn search(
+        &self,
+        embedding: &Embedding,
+        k: usize,
+        options: &QueryOptions,
+        filter: &dyn Fn(&Uuid) -> bool,
+    ) -> Vec<(Uuid, f32)> {
+        let k = min(k, self.len());
+        if k == 0 {
+            return vec![];
+        }
+
+        let ef_search = options.ef_search.unwrap_or(self.ef_search);
+
+        let mut pool = k;
+        loop {
+            let knbn = min(pool, self.last_id);
+            let ef = max(knbn, ef_search);
+
+            let neighbours: Vec<_> =
+                with_graph!(&self.idx, hnsw => hnsw.search(embedding.get(), knbn, ef))
+                    .into_iter()
+                    .filter_map(|neigh| {
+                        self.id_to_uuid
+                            .get(&neigh.get_origin_id())
+                            .map(|&uuid| (uuid, neigh.distance))
+                    })
+                    .filter(|(uuid, _dist)| filter(uuid))
+                    .take(k)
+                    .collect();
+
+            if neighbours.len() >= k || knbn >= self.last_id {
+                return neighbours;
+            }
+
+            pool = pool.saturating_mul(2);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use uuid::Uuid;
+
+    use crate::{
+        index::{IndexConfig, IndexEntry, IndexError, VectorIndex},
+        Embedding, QueryOptions,
+    };
+
+    use super::HnswIndex;
+
+    fn entry(v: &[f32]) -> IndexEntry {
+        IndexEntry {
+            e: Embedding::new(v.to_vec()),
+            uuid: Uuid::new_v4(),
+        }
+    }
+
+    #[test]
+    pub fn test_hnsw_dimension_mismatch() {
+        let mut idx = HnswIndex::new(&IndexConfig::default());
+
+        idx.add(entry(&[1.0, 2.0, 3.0])).unwrap();
+
+        assert!(matches!(
+            idx.add(entry(&[1.0, 2.0])),
+            Err(IndexError::DimensionError {
+                expected: 3,
+                found: 2
+            })
+        ));
+        assert_eq!(idx.len(), 1);
+    }
+
+    #[test]
+    pub fn test_hnsw_add_existing_uuid() {
+        let mut idx = HnswIndex::new(&IndexConfig::default());
+
+        let first = entry(&[1.0, 0.0]);
+        let uuid = first.uuid;
+        idx.add(first).unwrap();
+        idx.add(entry(&[0.0, 1.0])).unwrap();
+        idx.add(IndexEntry {
+            e: Embedding::new(vec![0.6, 0.8]),
+            uuid,
+        })
+        .unwrap();
+
+        assert_eq!(idx.len(), 2);
+        assert_eq!(idx.tombstones(), 1);
+
+        let res = idx.search(
+            &Embedding::new(vec![1.0, 0.0]),
+            3,
+            &QueryOptions::default(),
+            &|_| true,
+        );
+        assert_eq!(res.len(), 2);
+        assert_eq!(res.iter().filter(|(found, _)| *found == uuid).count(), 1);
+        // the distance is the one of the replacing vector
+        assert!((res[0].1 - 0.4).abs() < 1e-5);
+    }
+}
@@ -0,0 +1,539 @@
+use std::{
+    cmp::min,
+    collections::{HashMap, HashSet},
+    fmt::Debug,
+    fs::File,
+    io::{BufReader, BufWriter, Read, Write},
+    path::Path,
+    sync::Arc,
+};
+
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+use crate::{Embedding, QueryOptions};
+
+use super::{
+    flat::{load_quantizer, row_bytes},
+    kmeans::kmeans,
+    quantize::{training_points, Quantizer},
+    FlatIndex, IndexConfig, IndexEntry, IndexError, Metric, Quantization, VectorIndex,
+};
+
+// The coarse quantizer is trained once this many points per partition have been added.
+const TRAINING_POINTS_PER_LIST: usize = 32;
+
+/// Approximate index that partitions the embeddings around k-means centroids and only scans
+/// the `nprobe` partitions closest to the query.
+///
+/// Until enough points have been added to train the centroids, all points are kept in a single
+/// partition and searches are exact. With quantization enabled, the quantizer is trained at the
+/// same time and shared by all partitions.
+pub struct IvfIndex {
+    config: IndexConfig,
+    dimension: Option<usize>,
+    centroids: Vec<Vec<f32>>,
+    quantizer: Option<Arc<Quantizer>>,
+    lists: Vec<FlatIndex>,
+    assignments: HashMap<Uuid, usize>,
+}
+
+impl Debug for IvfIndex {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("IvfIndex")
+            .field("metric", &self.config.metric)
+            .field("nlist", &self.centroids.len())
+            .field("len", &self.len())
+            .finish()
+    }
+}
+
+impl IvfIndex {
+    pub fn new(config: &IndexConfig) -> Self {
+        let config = config.clone();
+
+        let mut idx = Self {
+            config,
+            dimension: None,
+            centroids: vec![],
+            quantizer: None,
+            lists: vec![],
+            assignments: HashMap::new(),
+        };
+        idx.lists = vec![FlatIndex::new(&idx.list_config())];
+
+        idx
+    }
+
+    pub fn is_trained(&self) -> bool {
+        !self.centroids.is_empty()
+    }
+
+    /// Deletes the files written by [`IvfIndex::dump`], if any.
+    pub fn remove_dump(basename: &Path) -> Result<(), IndexError> {
+        let basename = basename.to_string_lossy().to_string();
+
+        for suffix in [".ivf.json", ".ivf.data"] {
+            let path = format!("{basename}{suffix}");
+            if Path::new(&path).exists() {
+                std::fs::remove_file(path)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Loads an index previously written by [`IvfIndex::dump`]. Returns `None` if no dump
+    /// exists. Fails if the dump was written with a different configuration than `config`.
+    pub fn load(basename: &Path, config: &IndexConfig) -> Result<Option<Self>, IndexError> {
+        let basename = basename.to_string_lossy().to_string();
+
+        let meta_path = format!("{basename}.ivf.json");
+        let data_path = format!("{basename}.ivf.data");
+
+        if [&meta_path, &data_path]
+            .iter()
+            .any(|p| !Path::new(p).exists())
+        {
+            return Ok(None);
+        }
+
+        let meta: Value = serde_json::from_reader(BufReader::new(File::open(&meta_path)?))
+            .map_err(|e| IndexError::LoadError(e.into()))?;
+
+        let invalid = || IndexError::LoadError(format!("malformed index in {meta_path}").into());
+
+        let metric: Metric = meta["metric"].as_str().ok_or_else(invalid)?.parse()?;
+        if metric != config.metric {
+            return Err(IndexError::LoadError(
+                format!(
+                    "index was dumped with metric {metric}, expected {}",
+                    config.metric
+                )
+                .into(),
+            ));
+        }
+
+        let dimension = meta["dimension"].as_u64().map(|d| d as usize);
+        let ncentroids = meta["centroids"].as_u64().ok_or_else(invalid)? as usize;
+        let lists = meta["lists"]
+            .as_array()
+            .ok_or_else(invalid)?
+            .iter()
+            .map(|list| {
+                list.as_array()?
+                    .iter()
+                    .map(|uuid| uuid.as_str().and_then(|s| s.parse().ok()))
+                    .collect::<Option<Vec<Uuid>>>()
+            })
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(invalid)?;
+
+        if lists.len() != ncentroids.max(1) {
+            return Err(invalid());
+        }
+
+        let quantizer = load_quantizer(&meta["quantizer"])?;
+
+        let mut bytes = vec![];
+        BufReader::new(File::open(&data_path)?).read_to_end(&mut bytes)?;
+
+        let dim = dimension.unwrap_or(0);
+        let row_bytes = row_bytes(quantizer.as_deref(), dim);
+        let npoints: usize = lists.iter().map(Vec::len).sum();
+        if bytes.len() != ncentroids * dim * 4 + npoints * row_bytes {
+            return Err(IndexError::LoadError(
+                format!("{data_path} does not match {meta_path}").into(),
+            ));
+        }
+
+        let (centroids, mut rows) = bytes.split_at(ncentroids * dim * 4);
+
+        let mut idx = Self::new(config);
+        idx.dimension = dimension;
+        idx.quantizer = quantizer;
+        idx.centroids = centroids
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect::<Vec<_>>()
+            .chunks_exact(dim.max(1))
+            .map(<[f32]>::to_vec)
+            .collect();
+
+        let list_config = idx.list_config();
+        idx.lists = vec![];
+        for (list, uuids) in lists.into_iter().enumerate() {
+            let (list_rows, rest) = rows.split_at(uuids.len() * row_bytes);
+            rows = rest;
+
+            idx.assignments
+                .extend(uuids.iter().map(|uuid| (*uuid, list)));
+            idx.lists.push(FlatIndex::from_rows(
+                &list_config,
+                idx.quantizer.clone(),
+                dimension,
+                uuids,
+                list_rows,
+            )?);
+        }
+
+        Ok(Some(idx))
+    }
+
+    // Lists share the expected capacity of the index between them. They never train a
+    // quantizer themselves.
+    fn list_config(&self) -> IndexConfig {
+        IndexConfig {
+            capacity: self.config.capacity / self.config.nlist + 1,
+            quantization: Quantization::None,
+            ..self.config.clone()
+        }
+    }
+
+    fn nearest_lists(&self, v: &[f32], n: usize) -> Vec<usize> {
+        if !self.is_trained() {
+            return vec![0];
+        }
+
+        let mut lists: Vec<_> = self
+            .centroids
+            .iter()
+            .enumerate()
+            .map(|(list, c)| (list, self.config.metric.distance(v, c)))
+            .collect();
+
+        let n = min(n, lists.len());
+        lists.select_nth_unstable_by(n - 1, |a, b| a.1.total_cmp(&b.1));
+
+        lists.into_iter().take(n).map(|(list, _)| list).collect()
+    }
+
+    // Runs k-means over the buffered points and redistributes them over the new partitions.
+    fn train(&mut self) -> Result<(), IndexError> {
+        let buffer = std::mem::take(&mut self.lists)
+            .pop()
+            .expect("untrained index has one list");
+        let rows: Vec<_> = buffer.entries().collect();
+        let vectors: Vec<&[f32]> = rows.iter().map(|(_, v)| v.as_ref()).collect();
+
+        self.centroids = kmeans(self.config.metric, &vectors, self.config.nlist);
+        self.quantizer = Quantizer::train(
+            self.config.quantization,
+            &vectors,
+            self.config.pq_subvectors,
+        )
+        .map(Arc::new);
+        self.lists = (0..self.centroids.len())
+            .map(|_| FlatIndex::with_quantizer(&self.list_config(), self.quantizer.clone()))
+            .collect();
+
+        for (uuid, v) in rows {
+            let list = self.nearest_lists(&v, 1)[0];
+            self.lists[list].add(IndexEntry {
+                e: Embedding::new(v.to_vec()),
+                uuid: *uuid,
+            })?;
+            self.assignments.insert(*uuid, list);
+        }
+
+        Ok(())
+    }
+}
+
+impl VectorIndex for IvfIndex {
+    fn metric(&self) -> Metric {
+        self.config.metric
+    }
+
+    fn add(&mut self, entry: IndexEntry) -> Result<(), IndexError> {
+        let dim = entry.e.dim();
+        match self.dimension {
+            Some(dimension) if dimension != dim => {
+                return Err(IndexError::DimensionError {
+                    expected: dimension,
+                    found: dim,
+                })
+            }
+            _ => self.dimension = Some(dim),
+        }
+
+        if let Some(list) = self.assignments.remove(&entry.uuid) {
+            self.lists[list].remove(&HashSet::from([entry.uuid]));
+        }
+
+        let list = self.nearest_lists(entry.e.get(), 1)[0];
+        let uuid = entry.uuid;
+        self.lists[list].add(entry)?;
+        self.assignments.insert(uuid, list);
+
+        let threshold = (self.config.nlist * TRAINING_POINTS_PER_LIST)
+            .max(training_points(self.config.quantization));
+        if !self.is_trained() && self.len() >= threshold {
+            self.train()?;
+        }
+
+        Ok(())
+    }
+
+    fn remove(&mut self, uuids: &HashSet<Uuid>) -> usize {
+        let mut by_list: HashMap<usize, HashSet<Uuid>> = HashMap::new();
+        for uuid in uuids {
+            if let Some(list) = self.assignments.remove(uuid) {
+                by_list.entry(list).or_default().insert(*uuid);
+            }
+        }
+
+        by_list
+            .iter()
+            .map(|(list, uuids)| self.lists[*list].remove(uuids))
+            .sum()
+    }
+
+    fn len(&self) -> usize {
+        self.assignments.len()
+    }
+
+    fn dimension(&self) -> Option<usize> {
+        self.dimension
+    }
+
+    fn uuids(&self) -> Box<dyn Iterator<Item = &Uuid> + '_> {
+        Box::new(self.assignments.keys())
+    }
+
+    fn rescore_factor(&self) -> Option<usize> {
+        (self.config.quantization != Quantization::None).then_some(self.config.rescore)
+    }
+
+    /// Writes the partitions to `<basename>.ivf.json`, and the centroids as little-endian `f32`s
+    /// followed by the vectors of each partition to `<basename>.ivf.data`.
+    fn dump(&self, basename: &Path) -> Result<(), IndexError> {
+        let basename = basename.to_string_lossy().to_string();
+
+        let lists: Vec<Vec<String>> = self
+            .lists
+            .iter()
+            .map(|list| list.uuids().map(|uuid| uuid.to_string()).collect())
+            .collect();
+
+        let file = File::create(format!("{basename}.ivf.json"))?;
+        serde_json::to_writer(
+            BufWriter::new(file),
+            &json!({
+                "metric": self.config.metric.as_str(),
+                "dimension": self.dimension,
+                "centroids": self.centroids.len(),
+                "quantizer": self.quantizer.as_ref().map(|q| q.to_json()),
+                "lists": lists,
+            }),
+        )
+        .map_err(|e| IndexError::DumpError(e.into()))?;
+
+        let mut data_out = BufWriter::new(File::create(format!("{basename}.ivf.data"))?);
+        for x in self.centroids.iter().flatten() {
+            data_out.write_all(&x.to_le_bytes())?;
+        }
+        for list in &self.lists {
+            list.write_rows(&mut data_out)?;
+        }
+        data_out.flush()?;
+
+        Ok(())
+    }
+
+    /// Scans the `nprobe` partitions whose centroids are closest to `embedding`.
+    fn search(
+        &self,
+        embedding: &Embedding,
+        k: usize,
+        options: &QueryOptions,
+        filter: &dyn Fn(&Uuid) -> bool,
+    ) -> Vec<(Uuid, f32)> {
+        if k == 0 || self.dimension != Some(embedding.dim()) {
+            return vec![];
+        }
+
+        let nprobe = options.nprobe.unwrap_or(self.config.nprobe).max(1);
+
+        let mut neighbours: Vec<_> = self
+            .nearest_lists(embedding.get(), nprobe)
+            .into_iter()
+            .flat_map(|list| self.lists[list].search(embedding, k, options, filter))
+            .collect();
+
+        neighbours.sort_by(|a, b| a.1.total_cmp(&b.1));
+        neighbours.truncate(k);
+
+        neighbours
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+
+    use uuid::Uuid;
+
+    use crate::{
+        index::{
+            random_vectors, FlatIndex, IndexConfig, IndexEntry, Metric, Quantization, VectorIndex,
+        },
+        Embedding, QueryOptions,
+    };
+
+    use super::IvfIndex;
+
+    fn vectors(n: usize, dim: usize) -> Vec<Vec<f32>> {
+        random_vectors(n, dim, 42)
+    }
+
+    fn build(config: &IndexConfig, vectors: &[Vec<f32>]) -> (IvfIndex, FlatIndex) {
+        let mut ivf = IvfIndex::new(config);
+        let mut flat = FlatIndex::new(config);
+
+        for v in vectors {
+            let uuid = Uuid::new_v4();
+            ivf.add(IndexEntry {
+                e: Embedding::new(v.clone()),
+                uuid,
+            })
+            .unwrap();
+            flat.add(IndexEntry {
+                e: Embedding::new(v.clone()),
+                uuid,
+            })
+            .unwrap();
+        }
+
+        (ivf, flat)
+    }
+
+    #[test]
+    pub fn test_ivf_search() {
+        let config = IndexConfig {
+            metric: Metric::L2,
+            nlist: 16,
+            nprobe: 4,
+            ..Default::default()
+        };
+        let (ivf, flat) = build(&config, &vectors(2000, 16));
+
+        assert!(ivf.is_trained());
+        assert_eq!(ivf.len(), 2000);
+
+        let all = QueryOptions {
+            nprobe: Some(16),
+            ..Default::default()
+        };
+
+        let mut found = 0;
+        for query in vectors(20, 16).into_iter().map(Embedding::new) {
+            let expected = flat.search(&query, 10, &all, &|_| true);
+
+            // probing every partition is exact
+            assert_eq!(ivf.search(&query, 10, &all, &|_| true), expected);
+
+            let expected: HashSet<_> = expected.into_iter().map(|(uuid, _)| uuid).collect();
+            found += ivf
+                .search(&query, 10, &QueryOptions::default(), &|_| true)
+                .into_iter()
+                .filter(|(uuid, _)| expected.contains(uuid))
+                .count();
+        }
+
+        assert!(found >= 100, "recall too low: {found} / 200");
+    }
+
+    #[test]
+    pub fn test_ivf_untrained_is_exact() {
+        let config = IndexConfig {
+            nlist: 16,
+            ..Default::default()
+        };
+        let (ivf, flat) = build(&config, &vectors(100, 8));
+
+        assert!(!ivf.is_trained());
+
+        let query = Embedding::new(vec![0.1; 8]);
+        let options = QueryOptions::default();
+        assert_eq!(
+            ivf.search(&query, 5, &options, &|_| true),
+            flat.search(&query, 5, &options, &|_| true)
+        );
+    }
+
+    #[test]
+    pub fn test_ivf_remove() {
+        let config = IndexConfig {
+            nlist: 4,
+            ..Default::default()
+        };
+        let (mut ivf, _) = build(&config, &vectors(500, 8));
+
+        let removed: HashSet<_> = ivf.uuids().take(100).copied().collect();
+        assert_eq!(ivf.remove(&removed), 100);
+        assert_eq!(ivf.remove(&removed), 0);
+        assert_eq!(ivf.len(), 400);
+
+        let options = QueryOptions {
+            nprobe: Some(4),
+            ..Default::default()
+        };
+        let res = ivf.search(&Embedding::new(vec![0.0; 8]), 400, &options, &|_| true);
+        assert_eq!(res.len(), 400);
+        assert!(res.iter().all(|(uuid, _)| !removed.contains(uuid)));
+    }
+
+    #[test]
+    pub fn test_ivf_dump_and_load() {
+        let basename = std::env::temp_dir().join(format!("cedar-ivf-{}", Uuid::new_v4()));
+        let config = IndexConfig {
+            nlist: 8,
+            ..Default::default()
+        };
+        let (ivf, _) = build(&config, &vectors(1000, 8));
+        ivf.dump(&basename).unwrap();
+
+        let loaded = IvfIndex::load(&basename, &config).unwrap().unwrap();
+        IvfIndex::remove_dump(&basename).unwrap();
+
+        assert!(loaded.is_trained());
+        assert_eq!(loaded.len(), ivf.len());
+
+        let options = QueryOptions::default();
+        for query in vectors(5, 8).into_iter().map(Embedding::new) {
+            assert_eq!(
+                ivf.search(&query, 10, &options, &|_| true),
+                loaded.search(&query, 10, &options, &|_| true)
+            );
+        }
+    }
+
+    #[test]
+    pub fn test_ivf_quantized_dump_and_load() {
+        let basename = std::env::temp_dir().join(format!("cedar-ivf-{}", Uuid::new_v4()));
+        let config = IndexConfig {
+            nlist: 8,
+            quantization: Quantization::Product,
+            pq_subvectors: 4,
+            ..Default::default()
+        };
+        let (ivf, _) = build(&config, &vectors(2500, 8));
+        assert!(ivf.quantizer.is_some());
+        assert_eq!(ivf.rescore_factor(), Some(config.rescore));
+
+        ivf.dump(&basename).unwrap();
+        let loaded = IvfIndex::load(&basename, &config).unwrap().unwrap();
+        IvfIndex::remove_dump(&basename).unwrap();
+
+        assert_eq!(loaded.quantizer, ivf.quantizer);
+
+        let options = QueryOptions::default();
+        for query in vectors(5, 8).into_iter().map(Embedding::new) {
+            assert_eq!(
+                ivf.search(&query, 10, &options, &|_| true),
+                loaded.search(&query, 10, &options, &|_| true)
+            );
+        }
+    }
+}
@@ -0,0 +1,48 @@
+use std::cmp::min;
+
+use super::Metric;
+
+const KMEANS_ITERATIONS: usize = 10;
+
+/// Clusters `vectors` around at most `nlist` centroids.
+pub(super) fn kmeans(metric: Metric, vectors: &[&[f32]], nlist: usize) -> Vec<Vec<f32>> {
+    let nlist = min(nlist, vectors.len());
+    let dim = vectors.first().map_or(0, |v| v.len());
+
+    // Evenly spaced points make the initialisation deterministic.
+    let mut centroids: Vec<Vec<f32>> = (0..nlist)
+        .map(|i| vectors[i * vectors.len() / nlist].to_vec())
+        .collect();
+
+    for _ in 0..KMEANS_ITERATIONS {
+        let mut sums = vec![vec![0.0f32; dim]; nlist];
+        let mut counts = vec![0usize; nlist];
+
+        for v in vectors {
+            let c = nearest_centroid(metric, &centroids, v);
+            counts[c] += 1;
+            for (s, x) in sums[c].iter_mut().zip(v.iter()) {
+                *s += x;
+            }
+        }
+
+        // Empty partitions keep their previous centroid.
+        for ((centroid, sum), count) in centroids.iter_mut().zip(sums).zip(counts) {
+            if count > 0 {
+                *centroid = sum.into_iter().map(|s| s / count as f32).collect();
+            }
+        }
+    }
+
+    centroids
+}
+
+pub(super) fn nearest_centroid(metric: Metric, centroids: &[Vec<f32>], v: &[f32]) -> usize {
+    centroids
+        .iter()
+        .enumerate()
+        .map(|(c, centroid)| (c, metric.distance(v, centroid)))
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(c, _)| c)
+        .expect("at least one centroid")
+}
@@ -0,0 +1,245 @@
+use std::{
+    fs::{File, OpenOptions},
+    io,
+    path::{Path, PathBuf},
+};
+
+use memmap2::MmapMut;
+use uuid::Uuid;
+
+use super::IndexError;
+
+// Every file starts with the number of rows it holds, as a little-endian u64.
+const HEADER: usize = 8;
+// Number of rows the files have room for when they are created. They double when full.
+const INITIAL_ROWS: usize = 1024;
+// A uuid followed by the squared norm of the vector.
+const ID_BYTES: usize = 16 + 4;
+
+/// Growable array of fixed size rows in a memory-mapped file.
+struct MmapRows {
+    file: File,
+    mmap: MmapMut,
+    row_bytes: usize,
+    len: usize,
+}
+
+impl MmapRows {
+    fn create(path: &Path, row_bytes: usize) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        file.set_len((HEADER + INITIAL_ROWS * row_bytes) as u64)?;
+
+        // SAFETY: the file is owned by the index, nothing else is expected to resize or
+        // modify it while it is mapped.
+        let mmap = unsafe { MmapMut::map_mut(&file)? };
+
+        let mut rows = Self {
+            file,
+            mmap,
+            row_bytes,
+            len: 0,
+        };
+        rows.write_len();
+
+        Ok(rows)
+    }
+
+    fn open(path: &Path, row_bytes: usize) -> Result<Self, IndexError> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+
+        // SAFETY: see `MmapRows::create`.
+        let mmap = unsafe { MmapMut::map_mut(&file)? };
+
+        let invalid = || IndexError::LoadError(format!("truncated file {}", path.display()).into());
+        let header = mmap.get(..HEADER).ok_or_else(invalid)?;
+        let len = u64::from_le_bytes(header.try_into().expect("header is 8 bytes")) as usize;
+        if mmap.len() < HEADER + len * row_bytes {
+            return Err(invalid());
+        }
+
+        Ok(Self {
+            file,
+            mmap,
+            row_bytes,
+            len,
+        })
+    }
+
+    fn capacity(&self) -> usize {
+        (self.mmap.len() - HEADER) / self.row_bytes.max(1)
+    }
+
+    fn row(&self, i: usize) -> &[u8] {
+        let start = HEADER + i * self.row_bytes;
+        &self.mmap[start..start + self.row_bytes]
+    }
+
+    fn row_mut(&mut self, i: usize) -> &mut [u8] {
+        let start = HEADER + i * self.row_bytes;
+        &mut self.mmap[start..start + self.row_bytes]
+    }
+
+    // Appends a row, growing the file if it is full, and returns its index. The row has to
+    // be written by the caller.
+    fn push(&mut self) -> io::Result<usize> {
+        if self.len == self.capacity() {
+            let rows = (self.capacity() * 2).max(INITIAL_ROWS);
+            self.file.set_len((HEADER + rows * self.row_bytes) as u64)?;
+            // SAFETY: see `MmapRows::create`.
+            self.mmap = unsafe { MmapMut::map_mut(&self.file)? };
+        }
+
+        self.len += 1;
+        self.write_len();
+
+        Ok(self.len - 1)
+    }
+
+    // Moves the last row into `i`.
+    fn swap_remove(&mut self, i: usize) {
+        let last = self.len - 1;
+        if i != last {
+            let start = HEADER + last * self.row_bytes;
+            self.mmap
+                .copy_within(start..start + self.row_bytes, HEADER + i * self.row_bytes);
+        }
+
+        self.len = last;
+        self.write_len();
+    }
+
+    fn write_len(&mut self) {
+        self.mmap[..HEADER].copy_from_slice(&(self.len as u64).to_le_bytes());
+    }
+}
+
+/// Vectors of a flat index kept in memory-mapped files instead of on the heap.
+///
+/// `<basename>.flat.vectors` holds the vectors and `<basename>.flat.ids` holds the uuid and
+/// squared norm of each of them, so that reopening an index only reads the ids. Vectors are
+/// paged in by the operating system as searches touch them. Both files are updated in place
+/// and use the native byte order, so they are not portable between architectures.
+pub(crate) struct MappedVectors {
+    dimension: usize,
+    vectors: MmapRows,
+    ids: MmapRows,
+}
+
+impl MappedVectors {
+    /// Creates empty files for vectors of `dimension`, replacing existing ones.
+    pub(crate) fn create(basename: &Path, dimension: usize) -> Result<Self, IndexError> {
+        if let Some(dir) = basename.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+
+        Ok(Self {
+            dimension,
+            vectors: MmapRows::create(&vectors_path(basename), dimension * 4)?,
+            ids: MmapRows::create(&ids_path(basename), ID_BYTES)?,
+        })
+    }
+
+    /// Maps the files written for vectors of `dimension`.
+    pub(crate) fn open(basename: &Path, dimension: usize) -> Result<Self, IndexError> {
+        let vectors = MmapRows::open(&vectors_path(basename), dimension * 4)?;
+        let ids = MmapRows::open(&ids_path(basename), ID_BYTES)?;
+
+        if vectors.len != ids.len {
+            return Err(IndexError::LoadError(
+                format!(
+                    "{} holds {} vectors but {} ids",
+                    basename.display(),
+                    vectors.len,
+                    ids.len
+                )
+                .into(),
+            ));
+        }
+
+        Ok(Self {
+            dimension,
+            vectors,
+            ids,
+        })
+    }
+
+    /// Deletes the files of `basename`, if any.
+    pub(crate) fn remove(basename: &Path) -> Result<(), IndexError> {
+        for path in [vectors_path(basename), ids_path(basename)] {
+            if path.exists() {
+                std::fs::remove_file(path)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.ids.len
+    }
+
+    pub(crate) fn vector(&self, slot: usize) -> &[f32] {
+        let row = self.vectors.row(slot);
+        // SAFETY: the map is page aligned and rows start at multiples of 4 bytes, so the row
+        // is a properly aligned buffer of `dimension` f32s, which are valid for any bits.
+        unsafe { std::slice::from_raw_parts(row.as_ptr().cast::<f32>(), self.dimension) }
+    }
+
+    /// Returns the uuid and the squared norm stored in `slot`.
+    pub(crate) fn entry(&self, slot: usize) -> (Uuid, f32) {
+        let row = self.ids.row(slot);
+        let uuid = Uuid::from_bytes(row[..16].try_into().expect("uuids are 16 bytes"));
+        let norm = f32::from_ne_bytes(row[16..].try_into().expect("norms are 4 bytes"));
+
+        (uuid, norm)
+    }
+
+    pub(crate) fn push(&mut self, uuid: Uuid, v: &[f32], norm: f32) -> Result<(), IndexError> {
+        let slot = self.vectors.push()?;
+        self.ids.push()?;
+        self.set(slot, uuid, v, norm);
+
+        Ok(())
+    }
+
+    pub(crate) fn set(&mut self, slot: usize, uuid: Uuid, v: &[f32], norm: f32) {
+        for (bytes, x) in self.vectors.row_mut(slot).chunks_exact_mut(4).zip(v) {
+            bytes.copy_from_slice(&x.to_ne_bytes());
+        }
+
+        let row = self.ids.row_mut(slot);
+        row[..16].copy_from_slice(uuid.as_bytes());
+        row[16..].copy_from_slice(&norm.to_ne_bytes());
+    }
+
+    /// Moves the last vector into `slot`.
+    pub(crate) fn swap_remove(&mut self, slot: usize) {
+        self.vectors.swap_remove(slot);
+        self.ids.swap_remove(slot);
+    }
+
+    /// Writes modified pages back to the files.
+    pub(crate) fn flush(&self) -> Result<(), IndexError> {
+        self.vectors.mmap.flush()?;
+        self.ids.mmap.flush()?;
+
+        Ok(())
+    }
+}
+
+fn vectors_path(basename: &Path) -> PathBuf {
+    let mut path = basename.as_os_str().to_owned();
+    path.push(".flat.vectors");
+    path.into()
+}
+
+fn ids_path(basename: &Path) -> PathBuf {
+    let mut path = basename.as_os_str().to_owned();
+    path.push(".flat.ids");
+    path.into()
+}
@@ -1,72 +1,176 @@
-use std::{cmp::min, collections::HashMap, fmt::Debug};
+use std::{collections::HashSet, fmt::Debug, path::Path};
 
-use hnsw_rs::{hnsw::Hnsw, prelude::*};
 use uuid::Uuid;
 
-use crate::Embedding;
+use crate::{Embedding, QueryOptions};
 
-pub struct Index {
-    idx: Hnsw<f32, DistCosine>,
-    id_to_uuid: HashMap<usize, uuid::Uuid>,
-    last_id: usize,
-}
+mod config;
+mod flat;
+mod hnsw;
+mod ivf;
+mod kmeans;
+mod mmap;
+mod quantize;
 
-impl Debug for Index {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("Index").finish()
-    }
-}
+pub use config::{
+    DistInnerProduct, IndexConfig, IndexConfigBuilder, IndexKind, Metric, Quantization, Storage,
+};
+pub use flat::FlatIndex;
+pub use hnsw::HnswIndex;
+pub use ivf::IvfIndex;
 
 pub struct IndexEntry {
     pub(crate) e: Embedding,
     pub(crate) uuid: Uuid,
 }
 
-impl Index {
-    pub fn new() -> Self {
-        let idx = Hnsw::new(16, 100, 16, 200, DistCosine {});
-        let id_to_uuid = HashMap::new();
-        let last_id = 0;
-        let _num_elems = 0;
-
-        Self {
-            idx,
-            id_to_uuid,
-            last_id,
-        }
+/// A nearest neighbour index over the embeddings of one collection.
+pub trait VectorIndex: Debug + Send + Sync {
+    fn metric(&self) -> Metric;
+
+    /// Adds an entry to the index, replacing the entry with the same uuid if there is one.
+    /// Fails if the entry cannot be stored, e.g. because its dimension differs from the entries
+    /// already in the index.
+    fn add(&mut self, entry: IndexEntry) -> Result<(), IndexError>;
+
+    /// Removes the given uuids from the index. Returns the number of removed entries.
+    fn remove(&mut self, uuids: &HashSet<Uuid>) -> usize;
+
+    fn len(&self) -> usize;
+
+    /// Dimension of the entries, `None` until the first one is added.
+    fn dimension(&self) -> Option<usize>;
+
+    /// Number of removed entries that still take space in the index until it is rebuilt.
+    fn tombstones(&self) -> usize {
+        0
     }
 
-    pub fn add(&mut self, entry: IndexEntry) {
-        let id = self.last_id + 1;
-        self.last_id = id;
-        self.id_to_uuid.insert(id, entry.uuid);
-        self.idx.insert((entry.e.get(), id));
+    fn is_empty(&self) -> bool {
+        self.len() == 0
     }
 
-    pub fn nearest_k(&self, embedding: Embedding, k: usize) {
-        // TODO: magic number 30
-        self.idx.search(embedding.get(), k, 30);
+    fn uuids(&self) -> Box<dyn Iterator<Item = &Uuid> + '_>;
+
+    /// Whether searches always return the true nearest neighbours.
+    fn is_exact(&self) -> bool {
+        false
     }
 
-    pub fn get_nearest_neighbors(
+    /// For indexes that only keep compressed vectors, the number of candidates per requested
+    /// neighbour that should be rescored with the full precision embeddings.
+    fn rescore_factor(&self) -> Option<usize> {
+        None
+    }
+
+    /// Writes the index to files starting with `basename`.
+    fn dump(&self, basename: &Path) -> Result<(), IndexError>;
+
+    /// Returns up to `k` nearest neighbours of `embedding` whose uuid passes `filter`.
+    /// Approximate indexes read their search parameters from `options`.
+    fn search(
+        &self,
+        embedding: &Embedding,
+        k: usize,
+        options: &QueryOptions,
+        filter: &dyn Fn(&Uuid) -> bool,
+    ) -> Vec<(Uuid, f32)>;
+
+    fn distance(&self, a: &[f32], b: &[f32]) -> f32 {
+        self.metric().distance(a, b)
+    }
+
+    /// Runs [`VectorIndex::search`] for each of `embeddings`.
+    fn get_nearest_neighbors(
         &self,
         embeddings: &[Embedding],
         k: usize,
-        uuids: &[Uuid],
+        options: &QueryOptions,
+        filter: &dyn Fn(&Uuid) -> bool,
     ) -> Vec<Vec<(Uuid, f32)>> {
-        let k = min(k, self.last_id);
-
         embeddings
             .iter()
-            .map(|embedding| {
-                self.idx
-                    .search(embedding.get(), k, 30)
-                    .into_iter()
-                    .map(|neigh| (neigh.get_origin_id(), neigh.distance))
-                    .filter_map(|(id, dist)| self.id_to_uuid.get(&id).map(|&uuid| (uuid, dist)))
-                    .filter(|(uuid, _dist)| uuids.contains(uuid))
-                    .collect()
-            })
+            .map(|embedding| self.search(embedding, k, options, filter))
             .collect()
     }
 }
+
+/// Creates an empty index of the kind selected by `config`. Indexes with
+/// [`Storage::Mmap`] keep their vectors in files starting with `basename`, so it is required
+/// for them.
+pub fn new_index(
+    config: &IndexConfig,
+    basename: Option<&Path>,
+) -> Result<Box<dyn VectorIndex>, IndexError> {
+    Ok(match (config.kind, config.storage) {
+        (IndexKind::Flat, Storage::Mmap) => {
+            let basename = basename.ok_or_else(|| {
+                IndexError::ConfigError(
+                    "mmap storage requires a database opened from a file".to_string(),
+                )
+            })?;
+            Box::new(FlatIndex::mapped(config, basename)?)
+        }
+        (IndexKind::Hnsw, _) => Box::new(HnswIndex::new(config)),
+        (IndexKind::Flat, _) => Box::new(FlatIndex::new(config)),
+        (IndexKind::Ivf, _) => Box::new(IvfIndex::new(config)),
+    })
+}
+
+/// Loads an index of the kind selected by `config` from files starting with `basename`.
+/// Returns `None` if no dump exists.
+pub fn load_index(
+    basename: &Path,
+    config: &IndexConfig,
+) -> Result<Option<Box<dyn VectorIndex>>, IndexError> {
+    Ok(match config.kind {
+        IndexKind::Hnsw => {
+            HnswIndex::load(basename, config)?.map(|idx| Box::new(idx) as Box<dyn VectorIndex>)
+        }
+        IndexKind::Flat => {
+            FlatIndex::load(basename, config)?.map(|idx| Box::new(idx) as Box<dyn VectorIndex>)
+        }
+        IndexKind::Ivf => {
+            IvfIndex::load(basename, config)?.map(|idx| Box::new(idx) as Box<dyn VectorIndex>)
+        }
+    })
+}
+
+/// Deletes the files written by any kind of index for `basename`.
+pub fn remove_dump(basename: &Path) -> Result<(), IndexError> {
+    HnswIndex::remove_dump(basename)?;
+    FlatIndex::remove_dump(basename)?;
+    IvfIndex::remove_dump(basename)
+}
+
+/// Deterministic pseudo-random vectors with components in [-0.5, 0.5).
+#[cfg(test)]
+pub(crate) fn random_vectors(n: usize, dim: usize, seed: u64) -> Vec<Vec<f32>> {
+    let mut state = seed;
+    let mut next = || {
+        state = state
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        (state >> 40) as f32 / (1u64 << 24) as f32 - 0.5
+    };
+
+    (0..n).map(|_| (0..dim).map(|_| next()).collect()).collect()
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum IndexError {
+    #[error("Failed to dump index: {0}")]
+    DumpError(Box<dyn std::error::Error + Send + Sync>),
+
+    #[error("Failed to load index: {0}")]
+    LoadError(Box<dyn std::error::Error + Send + Sync>),
+
+    #[error("I/O error: {0}")]
+    IoError(#[from] std::io::Error),
+
+    #[error("Invalid index configuration: {0}")]
+    ConfigError(String),
+
+    #[error("Expected an embedding of dimension {expected}, found {found}")]
+    DimensionError { expected: usize, found: usize },
+}
@@ -0,0 +1,401 @@
+use serde_json::{json, Value};
+
+use super::{flat::dot, kmeans::kmeans, IndexError, Metric, Quantization};
+
+// Codes are single bytes, so codebooks have at most this many centroids.
+const PQ_CENTROIDS: usize = 256;
+
+/// Number of points a quantizer is trained on before vectors are compressed.
+pub(crate) fn training_points(quantization: Quantization) -> usize {
+    match quantization {
+        Quantization::None => 0,
+        Quantization::Scalar | Quantization::Binary => 1024,
+        Quantization::Product => 2048,
+    }
+}
+
+/// Compresses vectors into byte codes. Distances computed from codes are approximate.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Quantizer {
+    /// One byte per dimension, spread between the minimum and maximum seen in training.
+    Scalar { min: Vec<f32>, scale: Vec<f32> },
+    /// One byte per subvector, indexing the closest centroid of the subvector's codebook.
+    /// Subvector `j` covers dimensions `bounds[j]..bounds[j + 1]`.
+    Product {
+        bounds: Vec<usize>,
+        codebooks: Vec<Vec<f32>>,
+    },
+    /// One bit per dimension, set if the value is above the dimension's mean in training.
+    Binary { thresholds: Vec<f32> },
+}
+
+/// Precomputed per-query state to score codes against the query.
+pub(crate) enum QueryTable {
+    Scalar { offset: f32, weights: Vec<f32> },
+    Product { table: Vec<f32> },
+    Binary { code: Vec<u8> },
+}
+
+/// Approximate similarity of a code to the query of a [`QueryTable`].
+pub(crate) enum Score {
+    /// Approximation of the inner product `<query, v>`.
+    Dot(f32),
+    /// Number of bits that differ between the codes of the query and `v`.
+    Hamming(u32),
+}
+
+impl QueryTable {
+    pub(crate) fn score(&self, code: &[u8]) -> Score {
+        match self {
+            QueryTable::Scalar { offset, weights } => Score::Dot(
+                offset
+                    + weights
+                        .iter()
+                        .zip(code)
+                        .map(|(w, c)| w * *c as f32)
+                        .sum::<f32>(),
+            ),
+            QueryTable::Product { table } => Score::Dot(
+                code.iter()
+                    .enumerate()
+                    .map(|(j, c)| table[j * PQ_CENTROIDS + *c as usize])
+                    .sum(),
+            ),
+            QueryTable::Binary { code: query } => Score::Hamming(hamming(query, code)),
+        }
+    }
+}
+
+impl Quantizer {
+    /// Trains a quantizer on `vectors`. Returns `None` if `quantization` is disabled.
+    pub(crate) fn train(
+        quantization: Quantization,
+        vectors: &[&[f32]],
+        subvectors: usize,
+    ) -> Option<Self> {
+        let dim = vectors.first().map_or(0, |v| v.len());
+
+        match quantization {
+            Quantization::None => None,
+            Quantization::Scalar => {
+                let mut min = vec![f32::INFINITY; dim];
+                let mut max = vec![f32::NEG_INFINITY; dim];
+                for v in vectors {
+                    for (i, x) in v.iter().enumerate() {
+                        min[i] = min[i].min(*x);
+                        max[i] = max[i].max(*x);
+                    }
+                }
+
+                let scale = min
+                    .iter()
+                    .zip(&max)
+                    .map(|(lo, hi)| if hi > lo { (hi - lo) / 255.0 } else { 1.0 })
+                    .collect();
+
+                Some(Quantizer::Scalar { min, scale })
+            }
+            Quantization::Product => {
+                let m = subvectors.clamp(1, dim.max(1));
+                let bounds: Vec<usize> = (0..=m).map(|j| j * dim / m).collect();
+
+                let codebooks = bounds
+                    .windows(2)
+                    .map(|b| {
+                        let sub: Vec<&[f32]> = vectors.iter().map(|v| &v[b[0]..b[1]]).collect();
+                        kmeans(Metric::L2, &sub, PQ_CENTROIDS).concat()
+                    })
+                    .collect();
+
+                Some(Quantizer::Product { bounds, codebooks })
+            }
+            Quantization::Binary => {
+                let mut thresholds = vec![0.0f32; dim];
+                for v in vectors {
+                    for (t, x) in thresholds.iter_mut().zip(v.iter()) {
+                        *t += x;
+                    }
+                }
+                for t in &mut thresholds {
+                    *t /= vectors.len().max(1) as f32;
+                }
+
+                Some(Quantizer::Binary { thresholds })
+            }
+        }
+    }
+
+    pub(crate) fn code_size(&self) -> usize {
+        match self {
+            Quantizer::Scalar { min, .. } => min.len(),
+            Quantizer::Product { codebooks, .. } => codebooks.len(),
+            Quantizer::Binary { thresholds } => thresholds.len().div_ceil(8),
+        }
+    }
+
+    pub(crate) fn encode(&self, v: &[f32], out: &mut Vec<u8>) {
+        match self {
+            Quantizer::Scalar { min, scale } => out.extend(
+                v.iter()
+                    .zip(min.iter().zip(scale))
+                    .map(|(x, (lo, s))| ((x - lo) / s).round().clamp(0.0, 255.0) as u8),
+            ),
+            Quantizer::Product { bounds, codebooks } => {
+                for (b, codebook) in bounds.windows(2).zip(codebooks) {
+                    let sub = &v[b[0]..b[1]];
+                    let code = codebook
+                        .chunks_exact((b[1] - b[0]).max(1))
+                        .map(|c| {
+                            sub.iter()
+                                .zip(c)
+                                .map(|(x, y)| (x - y) * (x - y))
+                                .sum::<f32>()
+                        })
+                        .enumerate()
+                        .min_by(|a, b| a.1.total_cmp(&b.1))
+                        .map_or(0, |(c, _)| c);
+                    out.push(code as u8);
+                }
+            }
+            Quantizer::Binary { thresholds } => {
+                for (x, t) in v.chunks(8).zip(thresholds.chunks(8)) {
+                    let byte = x
+                        .iter()
+                        .zip(t)
+                        .enumerate()
+                        .filter(|(_, (x, t))| x > t)
+                        .fold(0u8, |byte, (bit, _)| byte | 1u8 << bit);
+                    out.push(byte);
+                }
+            }
+        }
+    }
+
+    pub(crate) fn decode(&self, code: &[u8]) -> Vec<f32> {
+        match self {
+            Quantizer::Scalar { min, scale } => code
+                .iter()
+                .zip(min.iter().zip(scale))
+                .map(|(c, (lo, s))| lo + *c as f32 * s)
+                .collect(),
+            Quantizer::Product { bounds, codebooks } => bounds
+                .windows(2)
+                .zip(codebooks)
+                .zip(code)
+                .flat_map(|((b, codebook), c)| {
+                    let sub_dim = b[1] - b[0];
+                    &codebook[*c as usize * sub_dim..(*c as usize + 1) * sub_dim]
+                })
+                .copied()
+                .collect(),
+            // Binary codes only keep the sign of each dimension around its threshold.
+            Quantizer::Binary { thresholds } => (0..thresholds.len())
+                .map(|i| {
+                    if code[i / 8] & 1u8 << (i % 8) != 0 {
+                        1.0
+                    } else {
+                        -1.0
+                    }
+                })
+                .collect(),
+        }
+    }
+
+    pub(crate) fn query_table(&self, query: &[f32]) -> QueryTable {
+        match self {
+            Quantizer::Scalar { min, scale } => QueryTable::Scalar {
+                offset: dot(query, min),
+                weights: query.iter().zip(scale).map(|(q, s)| q * s).collect(),
+            },
+            Quantizer::Product { bounds, codebooks } => {
+                let mut table = vec![0.0; codebooks.len() * PQ_CENTROIDS];
+                for (j, (b, codebook)) in bounds.windows(2).zip(codebooks).enumerate() {
+                    let sub = &query[b[0]..b[1]];
+                    for (c, centroid) in codebook.chunks_exact((b[1] - b[0]).max(1)).enumerate() {
+                        table[j * PQ_CENTROIDS + c] = dot(sub, centroid);
+                    }
+                }
+
+                QueryTable::Product { table }
+            }
+            Quantizer::Binary { .. } => {
+                let mut code = Vec::with_capacity(self.code_size());
+                self.encode(query, &mut code);
+
+                QueryTable::Binary { code }
+            }
+        }
+    }
+
+    pub(crate) fn to_json(&self) -> Value {
+        match self {
+            Quantizer::Scalar { min, scale } => {
+                json!({ "type": "sq8", "min": min, "scale": scale })
+            }
+            Quantizer::Product { bounds, codebooks } => {
+                json!({ "type": "pq", "bounds": bounds, "codebooks": codebooks })
+            }
+            Quantizer::Binary { thresholds } => {
+                json!({ "type": "binary", "thresholds": thresholds })
+            }
+        }
+    }
+
+    pub(crate) fn from_json(value: &Value) -> Result<Self, IndexError> {
+        let invalid = || IndexError::LoadError(format!("malformed quantizer {value}").into());
+
+        let floats = |v: &Value| -> Option<Vec<f32>> {
+            v.as_array()?
+                .iter()
+                .map(|x| x.as_f64().map(|x| x as f32))
+                .collect()
+        };
+
+        match value["type"].as_str() {
+            Some("sq8") => Ok(Quantizer::Scalar {
+                min: floats(&value["min"]).ok_or_else(invalid)?,
+                scale: floats(&value["scale"]).ok_or_else(invalid)?,
+            }),
+            Some("pq") => Ok(Quantizer::Product {
+                bounds: value["bounds"]
+                    .as_array()
+                    .ok_or_else(invalid)?
+                    .iter()
+                    .map(|b| b.as_u64().map(|b| b as usize))
+                    .collect::<Option<_>>()
+                    .ok_or_else(invalid)?,
+                codebooks: value["codebooks"]
+                    .as_array()
+                    .ok_or_else(invalid)?
+                    .iter()
+                    .map(floats)
+                    .collect::<Option<_>>()
+                    .ok_or_else(invalid)?,
+            }),
+            Some("binary") => Ok(Quantizer::Binary {
+                thresholds: floats(&value["thresholds"]).ok_or_else(invalid)?,
+            }),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+fn hamming(a: &[u8], b: &[u8]) -> u32 {
+    let mut chunks_a = a.chunks_exact(8);
+    let mut chunks_b = b.chunks_exact(8);
+
+    let mut distance = 0;
+    for (x, y) in chunks_a.by_ref().zip(chunks_b.by_ref()) {
+        let x = u64::from_le_bytes(x.try_into().expect("chunk of 8 bytes"));
+        let y = u64::from_le_bytes(y.try_into().expect("chunk of 8 bytes"));
+        distance += (x ^ y).count_ones();
+    }
+
+    distance
+        + chunks_a
+            .remainder()
+            .iter()
+            .zip(chunks_b.remainder())
+            .map(|(x, y)| (x ^ y).count_ones())
+            .sum::<u32>()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::index::Quantization;
+
+    use super::{Quantizer, Score};
+
+    fn vectors(n: usize, dim: usize) -> Vec<Vec<f32>> {
+        (0..n)
+            .map(|i| {
+                (0..dim)
+                    .map(|j| ((i * 31 + j * 17) % 101) as f32 / 101.0 - 0.5)
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    pub fn test_scalar_quantizer() {
+        let vectors = vectors(500, 24);
+        let refs: Vec<&[f32]> = vectors.iter().map(Vec::as_slice).collect();
+        let q = Quantizer::train(Quantization::Scalar, &refs, 0).unwrap();
+
+        assert_eq!(q.code_size(), 24);
+
+        let query = &vectors[3];
+        let table = q.query_table(query);
+        for v in &vectors[..50] {
+            let mut code = vec![];
+            q.encode(v, &mut code);
+
+            let decoded = q.decode(&code);
+            assert!(v.iter().zip(&decoded).all(|(a, b)| (a - b).abs() < 0.01));
+
+            let exact: f32 = query.iter().zip(v).map(|(a, b)| a * b).sum();
+            let Score::Dot(dot) = table.score(&code) else {
+                panic!("expected an inner product");
+            };
+            assert!((dot - exact).abs() < 0.05);
+        }
+    }
+
+    #[test]
+    pub fn test_product_quantizer() {
+        let vectors = vectors(1000, 32);
+        let refs: Vec<&[f32]> = vectors.iter().map(Vec::as_slice).collect();
+        let q = Quantizer::train(Quantization::Product, &refs, 8).unwrap();
+
+        assert_eq!(q.code_size(), 8);
+
+        let query = &vectors[7];
+        let table = q.query_table(query);
+        for v in &vectors[..50] {
+            let mut code = vec![];
+            q.encode(v, &mut code);
+            assert_eq!(code.len(), 8);
+
+            let decoded = q.decode(&code);
+            let approx: f32 = query.iter().zip(&decoded).map(|(a, b)| a * b).sum();
+            let Score::Dot(dot) = table.score(&code) else {
+                panic!("expected an inner product");
+            };
+            assert!((dot - approx).abs() < 1e-4);
+        }
+
+        assert_eq!(Quantizer::from_json(&q.to_json()).unwrap(), q);
+    }
+
+    #[test]
+    pub fn test_binary_quantizer() {
+        let vectors = vectors(500, 20);
+        let refs: Vec<&[f32]> = vectors.iter().map(Vec::as_slice).collect();
+        let q = Quantizer::train(Quantization::Binary, &refs, 0).unwrap();
+
+        assert_eq!(q.code_size(), 3);
+
+        let mut code = vec![];
+        q.encode(&vectors[0], &mut code);
+        assert!(matches!(
+            q.query_table(&vectors[0]).score(&code),
+            Score::Hamming(0)
+        ));
+
+        // mirroring a vector around the thresholds flips every bit
+        let Quantizer::Binary { thresholds } = &q else {
+            panic!("expected a binary quantizer");
+        };
+        let mirrored: Vec<f32> = vectors[0]
+            .iter()
+            .zip(thresholds)
+            .map(|(x, t)| 2.0 * t - x + if x > t { -1e-3 } else { 1e-3 })
+            .collect();
+        assert!(matches!(
+            q.query_table(&mirrored).score(&code),
+            Score::Hamming(20)
+        ));
+
+        assert_eq!(Quantizer::from_json(&q.to_json()).unwrap(), q);
+    }
+}
@@ -1,3 +1,4 @@
+use derive_builder::Builder;
 use serde_json::Value;
 use uuid::Uuid;
 
@@ -5,8 +6,10 @@ pub mod client;
 pub mod collection;
 pub mod db;
 pub mod embeddings;
+pub mod filter;
 pub mod index;
 
+#[derive(Debug, Clone, PartialEq)]
 pub struct Embedding {
     e: Vec<f32>,
 }
@@ -77,11 +80,69 @@ impl Document {
     }
 }
 
+/// A document matched by a query. The uuid is always set, the fields that were not requested
+/// through [`QueryOptions::include`] are `None`.
 #[derive(Debug)]
 pub struct QueryResult {
     pub uuid: Uuid,
-    pub text: String,
-    pub distance: f32,
-    pub embedding: Vec<f32>,
-    pub metadata: serde_json::Value,
+    pub text: Option<String>,
+    pub distance: Option<f32>,
+    pub embedding: Option<Vec<f32>>,
+    pub metadata: Option<serde_json::Value>,
+}
+
+/// Fields returned for each document matched by a query besides its uuid. Everything but the
+/// embeddings is returned by default.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Include {
+    pub distances: bool,
+    pub texts: bool,
+    pub metadatas: bool,
+    pub embeddings: bool,
+}
+
+impl Include {
+    /// Returns every field, including the embeddings.
+    pub fn all() -> Self {
+        Self {
+            embeddings: true,
+            ..Default::default()
+        }
+    }
+
+    /// Returns only the uuids. Combine with struct update syntax to pick fields.
+    pub fn none() -> Self {
+        Self {
+            distances: false,
+            texts: false,
+            metadatas: false,
+            embeddings: false,
+        }
+    }
+}
+
+impl Default for Include {
+    fn default() -> Self {
+        Self {
+            distances: true,
+            texts: true,
+            metadatas: true,
+            embeddings: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Builder)]
+#[builder(default)]
+pub struct QueryOptions {
+    /// Overrides the collection's `ef_search` for this query. Higher values trade latency for
+    /// recall.
+    #[builder(setter(strip_option))]
+    pub ef_search: Option<usize>,
+    /// Overrides the number of partitions an IVF index searches for this query. Higher values
+    /// trade latency for recall.
+    #[builder(setter(strip_option))]
+    pub nprobe: Option<usize>,
+    /// Fields returned for each match.
+    pub include: Include,
 }